@@ -0,0 +1,586 @@
+//! The `.fqc` format: a lowered program saved so it can run later without
+//! its source or `vocabulary.json`.
+//!
+//! A file starts with the magic bytes `FQC\0`, the format version as a
+//! `u16`, and the length and FNV-1a checksum of the rest as `u32`s. The
+//! rest is the constant pool, the name table and the instructions, each a
+//! count followed by its entries. Fixed-size numbers are little-endian,
+//! counts, indices and other unsigned operands LEB128. Floats, strings and
+//! big integers are stored once in the pool and referred to by index.
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+mod error;
+pub use error::FormatError;
+use crate::ir::{MatchPattern, Program, IR};
+use crate::types::{ActionV, ComparsionV, FlowListener, FlowStreamer, Var};
+use crate::vm::VarV;
+
+const MAGIC: &[u8; 4] = b"FQC\0";
+/// Bumped whenever the encoding of anything changes.
+pub const VERSION: u16 = 1;
+/// Tuple patterns nested deeper than this are rejected rather than read
+/// recursively.
+const MAX_PATTERN_DEPTH: usize = 256;
+
+pub fn encode(program: &Program) -> Vec<u8> {
+    let mut pool = Pool::default();
+    let mut code: Vec<u8> = Vec::new();
+    write_uint(&mut code, program.code.len());
+    for instruction in &program.code {
+        write_instruction(&mut code, instruction, &mut pool);
+    }
+    let mut body: Vec<u8> = Vec::new();
+    write_uint(&mut body, pool.entries.len());
+    for entry in &pool.entries {
+        body.extend(entry);
+    }
+    write_uint(&mut body, program.names.len());
+    for name in &program.names {
+        write_str(&mut body, name);
+    }
+    body.extend(code);
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(VERSION.to_le_bytes());
+    bytes.extend((body.len() as u32).to_le_bytes());
+    bytes.extend(checksum(&body).to_le_bytes());
+    bytes.extend(body);
+    bytes
+}
+pub fn decode(bytes: &[u8]) -> Result<Program, FormatError> {
+    let mut header = Reader { bytes, at: 0, part: "header", last: 0 };
+    if bytes.len() < MAGIC.len() || header.take(MAGIC.len())? != MAGIC {
+        return Err(FormatError::NotBytecode);
+    }
+    let version = u16::from_le_bytes(header.array()?);
+    if version != VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+    let len = u32::from_le_bytes(header.array()?) as usize;
+    let sum = u32::from_le_bytes(header.array()?);
+    let body = &bytes[header.at..];
+    if body.len() < len {
+        return Err(FormatError::Truncated("program"));
+    }
+    if body.len() > len {
+        return Err(FormatError::TrailingBytes(body.len() - len));
+    }
+    if checksum(body) != sum {
+        return Err(FormatError::ChecksumMismatch);
+    }
+
+    let mut reader = Reader { bytes: body, at: 0, part: "constant pool", last: 0 };
+    let mut pool: Vec<VarV> = Vec::new();
+    for _ in 0..reader.uint()? {
+        pool.push(read_constant(&mut reader)?);
+    }
+    reader.part = "name table";
+    let mut names: Vec<String> = Vec::new();
+    for _ in 0..reader.uint()? {
+        names.push(reader.string()?);
+    }
+    reader.part = "code";
+    let mut code: Vec<IR> = Vec::new();
+    for _ in 0..reader.uint()? {
+        code.push(read_instruction(&mut reader, &pool)?);
+    }
+    if reader.at < body.len() {
+        return Err(FormatError::TrailingBytes(body.len() - reader.at));
+    }
+    let len = code.len();
+    for instruction in &mut code {
+        match instruction.target_mut() {
+            Some(target) if *target > len => return Err(FormatError::InvalidTarget(*target)),
+            _ => (),
+        }
+    }
+    Ok(Program { code, names })
+}
+
+/// Constants by their encoding, so that values that compare equal across
+/// types, such as `1` and `1.0`, still get entries of their own.
+#[derive(Default)]
+struct Pool {
+    entries: Vec<Vec<u8>>,
+    indices: HashMap<Vec<u8>, usize>,
+}
+impl Pool {
+    fn add(&mut self, value: &VarV) -> usize {
+        let mut entry: Vec<u8> = Vec::new();
+        match value {
+            VarV::Num(n) => {
+                entry.push(0);
+                entry.extend((*n as i64).to_le_bytes());
+            }
+            VarV::Float(n) => {
+                entry.push(1);
+                entry.extend(n.to_bits().to_le_bytes());
+            }
+            VarV::Str(s) => {
+                entry.push(2);
+                write_str(&mut entry, s);
+            }
+            VarV::Bool(b) => entry.extend([3, *b as u8]),
+            #[cfg(feature = "bigint")]
+            VarV::Big(n) => {
+                entry.push(4);
+                let digits = n.to_signed_bytes_le();
+                write_uint(&mut entry, digits.len());
+                entry.extend(digits);
+            }
+            VarV::Tuple(_) | VarV::List(_) => unreachable!("constants are never lists or tuples"),
+        }
+        if let Some(index) = self.indices.get(&entry) {
+            return *index;
+        }
+        self.entries.push(entry.clone());
+        self.indices.insert(entry, self.entries.len() - 1);
+        self.entries.len() - 1
+    }
+}
+fn write_instruction(out: &mut Vec<u8>, instruction: &IR, pool: &mut Pool) {
+    match instruction {
+        IR::Num(n) => {
+            out.push(0);
+            out.extend((*n as i64).to_le_bytes());
+        }
+        IR::Float(n) => {
+            out.push(1);
+            write_uint(out, pool.add(&VarV::Float(*n)));
+        }
+        #[cfg(feature = "bigint")]
+        IR::BigNum(n) => {
+            out.push(2);
+            write_uint(out, pool.add(&VarV::Big(n.clone())));
+        }
+        IR::Bool(b) => out.extend([3, *b as u8]),
+        IR::Str(s) => {
+            out.push(4);
+            write_uint(out, pool.add(&VarV::Str(s.clone())));
+        }
+        IR::Nil => out.push(5),
+        IR::BinExpr(action) => out.extend([6, action_code(action)]),
+        IR::Not => out.push(7),
+        IR::Or => out.push(8),
+        IR::And => out.push(9),
+        IR::Eql => out.push(10),
+        IR::NEql => out.push(11),
+        IR::Ls => out.push(12),
+        IR::Gt => out.push(13),
+        IR::LsEql => out.push(14),
+        IR::GtEql => out.push(15),
+        IR::Store(var) => {
+            out.push(16);
+            write_var(out, *var);
+        }
+        IR::Load(var) => {
+            out.push(17);
+            write_var(out, *var);
+        }
+        IR::Jump(target) => {
+            out.push(18);
+            write_uint(out, *target);
+        }
+        IR::Test(flag, target) => {
+            out.extend([19, *flag as u8]);
+            write_uint(out, *target);
+        }
+        IR::Unwind(depth, target) => {
+            out.push(20);
+            write_uint(out, *depth);
+            write_uint(out, *target);
+        }
+        IR::Enter(slots) => {
+            out.push(21);
+            write_uint(out, *slots);
+        }
+        IR::Leave => out.push(22),
+        IR::Function(name, arity, entry) => {
+            out.push(23);
+            write_uint(out, *name);
+            write_uint(out, *arity);
+            write_uint(out, *entry);
+        }
+        IR::Call(name, argc) => {
+            out.push(24);
+            write_uint(out, *name);
+            write_uint(out, *argc);
+        }
+        IR::Ret(has_value) => out.extend([25, *has_value as u8]),
+        IR::End => out.push(26),
+        IR::List(len) => {
+            out.push(27);
+            write_uint(out, *len);
+        }
+        IR::Index => out.push(28),
+        IR::Slice(has_start, has_end) => out.extend([29, *has_start as u8, *has_end as u8]),
+        IR::Len => out.push(30),
+        IR::Range => out.push(31),
+        IR::SetItem(depth) => {
+            out.push(32);
+            write_uint(out, *depth);
+        }
+        IR::Input(streamer) => out.extend([33, match *streamer.borrow() {
+            FlowStreamer::Console => 0,
+            FlowStreamer::None => 1,
+        }]),
+        // output always goes to the console once loaded
+        IR::Output(_) => out.push(34),
+        IR::Case(patterns, target) => {
+            out.push(35);
+            write_patterns(out, patterns, pool);
+            write_uint(out, *target);
+        }
+        IR::LoadBin(left, right, action) => {
+            out.extend([36, action_code(action)]);
+            write_var(out, *left);
+            write_var(out, *right);
+        }
+        IR::BinStore(action, var) => {
+            out.extend([37, action_code(action)]);
+            write_var(out, *var);
+        }
+        IR::JumpUnless(comparsion, target) => {
+            out.extend([38, comparsion_code(comparsion)]);
+            write_uint(out, *target);
+        }
+    }
+}
+fn read_instruction(reader: &mut Reader, pool: &[VarV]) -> Result<IR, FormatError> {
+    let instruction = match reader.byte()? {
+        0 => IR::Num(reader.int()?),
+        1 => match constant(reader, pool)? {
+            VarV::Float(n) => IR::Float(*n),
+            _ => return Err(FormatError::InvalidConstant(reader.last)),
+        },
+        #[cfg(feature = "bigint")]
+        2 => match constant(reader, pool)? {
+            VarV::Big(n) => IR::BigNum(n.clone()),
+            _ => return Err(FormatError::InvalidConstant(reader.last)),
+        },
+        3 => IR::Bool(reader.flag()?),
+        4 => match constant(reader, pool)? {
+            VarV::Str(s) => IR::Str(s.clone()),
+            _ => return Err(FormatError::InvalidConstant(reader.last)),
+        },
+        5 => IR::Nil,
+        6 => IR::BinExpr(read_action(reader)?),
+        7 => IR::Not,
+        8 => IR::Or,
+        9 => IR::And,
+        10 => IR::Eql,
+        11 => IR::NEql,
+        12 => IR::Ls,
+        13 => IR::Gt,
+        14 => IR::LsEql,
+        15 => IR::GtEql,
+        16 => IR::Store(read_var(reader)?),
+        17 => IR::Load(read_var(reader)?),
+        18 => IR::Jump(reader.uint()?),
+        19 => IR::Test(reader.flag()?, reader.uint()?),
+        20 => IR::Unwind(reader.uint()?, reader.uint()?),
+        21 => IR::Enter(reader.uint()?),
+        22 => IR::Leave,
+        23 => IR::Function(reader.uint()?, reader.uint()?, reader.uint()?),
+        24 => IR::Call(reader.uint()?, reader.uint()?),
+        25 => IR::Ret(reader.flag()?),
+        26 => IR::End,
+        27 => IR::List(reader.uint()?),
+        28 => IR::Index,
+        29 => IR::Slice(reader.flag()?, reader.flag()?),
+        30 => IR::Len,
+        31 => IR::Range,
+        32 => IR::SetItem(reader.uint()?),
+        33 => IR::Input(RefCell::new(match reader.byte()? {
+            0 => FlowStreamer::Console,
+            1 => FlowStreamer::None,
+            tag => return Err(FormatError::UnknownTag { what: "input", tag }),
+        })),
+        34 => IR::Output(RefCell::new(FlowListener::Console)),
+        35 => IR::Case(read_patterns(reader, pool, 0)?, reader.uint()?),
+        36 => {
+            let action = read_action(reader)?;
+            IR::LoadBin(read_var(reader)?, read_var(reader)?, action)
+        }
+        37 => IR::BinStore(read_action(reader)?, read_var(reader)?),
+        38 => IR::JumpUnless(read_comparsion(reader)?, reader.uint()?),
+        tag => return Err(FormatError::UnknownTag { what: "opcode", tag }),
+    };
+    Ok(instruction)
+}
+fn read_constant(reader: &mut Reader) -> Result<VarV, FormatError> {
+    let value = match reader.byte()? {
+        0 => VarV::Num(reader.int()?),
+        1 => VarV::Float(f64::from_bits(u64::from_le_bytes(reader.array()?))),
+        2 => VarV::Str(reader.string()?),
+        3 => VarV::Bool(reader.flag()?),
+        #[cfg(feature = "bigint")]
+        4 => {
+            let len = reader.uint()?;
+            VarV::Big(num_bigint::BigInt::from_signed_bytes_le(reader.take(len)?))
+        }
+        tag => return Err(FormatError::UnknownTag { what: "constant", tag }),
+    };
+    Ok(value)
+}
+/// The pool entry whose index is the next operand.
+fn constant<'p>(reader: &mut Reader, pool: &'p [VarV]) -> Result<&'p VarV, FormatError> {
+    let index = reader.uint()?;
+    reader.last = index;
+    pool.get(index).ok_or(FormatError::InvalidConstant(index))
+}
+fn action_code(action: &ActionV) -> u8 {
+    match action {
+        ActionV::Not => 0,
+        ActionV::And => 1,
+        ActionV::Or => 2,
+        ActionV::Add => 3,
+        ActionV::Sub => 4,
+        ActionV::Div => 5,
+        ActionV::Mul => 6,
+        ActionV::Mod => 7,
+    }
+}
+fn read_action(reader: &mut Reader) -> Result<ActionV, FormatError> {
+    let action = match reader.byte()? {
+        0 => ActionV::Not,
+        1 => ActionV::And,
+        2 => ActionV::Or,
+        3 => ActionV::Add,
+        4 => ActionV::Sub,
+        5 => ActionV::Div,
+        6 => ActionV::Mul,
+        7 => ActionV::Mod,
+        tag => return Err(FormatError::UnknownTag { what: "operation", tag }),
+    };
+    Ok(action)
+}
+fn comparsion_code(comparsion: &ComparsionV) -> u8 {
+    match comparsion {
+        ComparsionV::Equal => 0,
+        ComparsionV::NotEqual => 1,
+        ComparsionV::Less => 2,
+        ComparsionV::Greater => 3,
+        ComparsionV::LessOrEqual => 4,
+        ComparsionV::GreaterOrEqual => 5,
+    }
+}
+fn read_comparsion(reader: &mut Reader) -> Result<ComparsionV, FormatError> {
+    let comparsion = match reader.byte()? {
+        0 => ComparsionV::Equal,
+        1 => ComparsionV::NotEqual,
+        2 => ComparsionV::Less,
+        3 => ComparsionV::Greater,
+        4 => ComparsionV::LessOrEqual,
+        5 => ComparsionV::GreaterOrEqual,
+        tag => return Err(FormatError::UnknownTag { what: "comparison", tag }),
+    };
+    Ok(comparsion)
+}
+/// A count and that many patterns, each a tag and its operand; a tuple's
+/// operand is its own patterns.
+fn write_patterns(out: &mut Vec<u8>, patterns: &[MatchPattern], pool: &mut Pool) {
+    write_uint(out, patterns.len());
+    for pattern in patterns {
+        match pattern {
+            MatchPattern::Var(var) => {
+                out.push(0);
+                write_var(out, *var);
+            }
+            MatchPattern::Val(value) => {
+                out.push(1);
+                write_uint(out, pool.add(value));
+            }
+            MatchPattern::Unused => out.push(2),
+            MatchPattern::Tuple(patterns) => {
+                out.push(3);
+                write_patterns(out, patterns, pool);
+            }
+        }
+    }
+}
+fn read_patterns(reader: &mut Reader, pool: &[VarV], depth: usize) -> Result<Vec<MatchPattern>, FormatError> {
+    if depth > MAX_PATTERN_DEPTH {
+        return Err(FormatError::OutOfRange("pattern nesting"));
+    }
+    let mut patterns: Vec<MatchPattern> = Vec::new();
+    for _ in 0..reader.uint()? {
+        patterns.push(match reader.byte()? {
+            0 => MatchPattern::Var(read_var(reader)?),
+            1 => MatchPattern::Val(constant(reader, pool)?.clone()),
+            2 => MatchPattern::Unused,
+            3 => MatchPattern::Tuple(read_patterns(reader, pool, depth + 1)?),
+            tag => return Err(FormatError::UnknownTag { what: "pattern", tag }),
+        });
+    }
+    Ok(patterns)
+}
+fn write_var(out: &mut Vec<u8>, var: Var) {
+    write_uint(out, var.name);
+    write_uint(out, var.depth);
+    write_uint(out, var.slot);
+}
+fn read_var(reader: &mut Reader) -> Result<Var, FormatError> {
+    Ok(Var { name: reader.uint()?, depth: reader.uint()?, slot: reader.uint()? })
+}
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_uint(out, s.len());
+    out.extend(s.as_bytes());
+}
+fn write_uint(out: &mut Vec<u8>, value: usize) {
+    let mut value = value as u64;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+/// FNV-1a, enough to notice damage, not tampering.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, byte| (hash ^ *byte as u32).wrapping_mul(0x0100_0193))
+}
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+    /// What is being read, for `FormatError::Truncated`.
+    part: &'static str,
+    /// The last constant index read, for `FormatError::InvalidConstant`.
+    last: usize,
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        let end = self.at.checked_add(len).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or(FormatError::Truncated(self.part))?;
+        let bytes = &self.bytes[self.at..end];
+        self.at = end;
+        Ok(bytes)
+    }
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        Ok(self.take(N)?.try_into().expect("took exactly N bytes"))
+    }
+    fn byte(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+    fn flag(&mut self) -> Result<bool, FormatError> {
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(FormatError::UnknownTag { what: "flag", tag }),
+        }
+    }
+    fn int(&mut self) -> Result<isize, FormatError> {
+        let value = i64::from_le_bytes(self.array()?);
+        isize::try_from(value).map_err(|_| FormatError::OutOfRange("integer"))
+    }
+    fn uint(&mut self) -> Result<usize, FormatError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = (byte & 0x7f) as u64;
+            if shift == 63 && bits > 1 {
+                break;
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return usize::try_from(value).map_err(|_| FormatError::OutOfRange("operand"));
+            }
+        }
+        Err(FormatError::OutOfRange("operand"))
+    }
+    fn string(&mut self) -> Result<String, FormatError> {
+        let len = self.uint()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| FormatError::InvalidString)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::inout::{read_json, Vocabulary};
+    use crate::ir::{assemble, ast_to_ir, disassemble};
+    use crate::lexer::tokenize_code;
+    use crate::parser::parse_program;
+
+    /// Where the body starts: the magic, the version, the length and the
+    /// checksum.
+    const HEADER: usize = 14;
+
+    /// `code/<name>.fq` lowered as `--compile` does.
+    fn sample(name: &str) -> Program {
+        let vocabulary: Vocabulary = read_json("vocabulary.json".to_string());
+        let text = fs::read_to_string(format!("code/{}.fq", name)).expect("cannot read sample");
+        let (mut diagnostics, mut names) = (Vec::new(), Vec::new());
+        let tokens = tokenize_code(text, &vocabulary, None, Some(&mut names), &mut diagnostics);
+        let listener = RefCell::new(FlowListener::Console);
+        let tree = parse_program(&tokens, &mut diagnostics, &listener).expect("sample does not parse");
+        let code = ast_to_ir(std::slice::from_ref(&tree)).expect("sample lowers to invalid code");
+        Program { code, names }
+    }
+    /// Two instructions, no names and no constants, so the first opcode
+    /// is the fourth byte of the body.
+    fn small() -> Vec<u8> {
+        encode(&assemble("num 1\njump L2\nL2:").expect("test listing does not assemble"))
+    }
+    /// Rewrites the length and checksum to match an edited body.
+    fn reseal(mut bytes: Vec<u8>) -> Vec<u8> {
+        let body = bytes[HEADER..].to_vec();
+        bytes[6..10].copy_from_slice(&(body.len() as u32).to_le_bytes());
+        bytes[10..14].copy_from_slice(&checksum(&body).to_le_bytes());
+        bytes
+    }
+    #[test]
+    fn round_trips_samples() {
+        // patterns, guards and string, float and integer constants
+        for name in ["match", "folding", "functions", "grid"] {
+            let program = sample(name);
+            let bytes = encode(&program);
+            let decoded = decode(&bytes).expect("encoded sample does not decode");
+            assert_eq!(decoded.names, program.names);
+            assert_eq!(disassemble(&decoded), disassemble(&program));
+            assert_eq!(encode(&decoded), bytes);
+        }
+    }
+    #[test]
+    fn rejects_a_damaged_header() {
+        let mut bytes = small();
+        bytes[0] = b'X';
+        assert_eq!(decode(&bytes).err(), Some(FormatError::NotBytecode));
+        let mut bytes = small();
+        bytes[4..6].copy_from_slice(&2u16.to_le_bytes());
+        assert_eq!(decode(&bytes).err(), Some(FormatError::UnsupportedVersion(2)));
+        assert_eq!(decode(&small()[..8]).err(), Some(FormatError::Truncated("header")));
+    }
+    #[test]
+    fn rejects_a_body_of_the_wrong_length() {
+        let bytes = small();
+        assert_eq!(decode(&bytes[..bytes.len() - 1]).err(), Some(FormatError::Truncated("program")));
+        let mut bytes = small();
+        bytes.push(0);
+        assert_eq!(decode(&bytes).err(), Some(FormatError::TrailingBytes(1)));
+        let mut bytes = small();
+        bytes.pop();
+        assert_eq!(decode(&reseal(bytes)).err(), Some(FormatError::Truncated("code")));
+    }
+    #[test]
+    fn rejects_a_changed_body() {
+        let mut bytes = small();
+        *bytes.last_mut().unwrap() ^= 1;
+        assert_eq!(decode(&bytes).err(), Some(FormatError::ChecksumMismatch));
+    }
+    #[test]
+    fn rejects_an_unknown_opcode() {
+        let mut bytes = small();
+        bytes[HEADER + 3] = 0xff;
+        assert_eq!(decode(&reseal(bytes)).err(), Some(FormatError::UnknownTag { what: "opcode", tag: 0xff }));
+    }
+    #[test]
+    fn rejects_a_target_past_the_end() {
+        let program = Program { code: vec![IR::Num(1), IR::Jump(3)], names: Vec::new() };
+        assert_eq!(decode(&encode(&program)).err(), Some(FormatError::InvalidTarget(3)));
+    }
+}
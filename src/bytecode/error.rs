@@ -0,0 +1,41 @@
+use std::fmt;
+
+/// Why a `.fqc` file could not be loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    /// The file does not start with the magic bytes.
+    NotBytecode,
+    UnsupportedVersion(u16),
+    /// The file ends in the middle of the named part.
+    Truncated(&'static str),
+    ChecksumMismatch,
+    /// A tag byte that does not name any opcode, constant or pattern.
+    UnknownTag { what: &'static str, tag: u8 },
+    /// A constant pool index past the end of the pool, or naming a
+    /// constant of the wrong type.
+    InvalidConstant(usize),
+    /// A jump target past the end of the code.
+    InvalidTarget(usize),
+    InvalidString,
+    /// A number that does not fit into the type it is read as.
+    OutOfRange(&'static str),
+    TrailingBytes(usize),
+}
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::NotBytecode => write!(f, "not a compiled program"),
+            FormatError::UnsupportedVersion(version) => {
+                write!(f, "format version {} is not supported, expected {}", version, super::VERSION)
+            }
+            FormatError::Truncated(part) => write!(f, "file ends inside the {}", part),
+            FormatError::ChecksumMismatch => write!(f, "checksum mismatch, the file is corrupt"),
+            FormatError::UnknownTag { what, tag } => write!(f, "unknown {} tag {}", what, tag),
+            FormatError::InvalidConstant(index) => write!(f, "invalid constant #{}", index),
+            FormatError::InvalidTarget(target) => write!(f, "jump target {} is out of range", target),
+            FormatError::InvalidString => write!(f, "string is not valid UTF-8"),
+            FormatError::OutOfRange(what) => write!(f, "{} is out of range", what),
+            FormatError::TrailingBytes(len) => write!(f, "{} unexpected bytes after the code", len),
+        }
+    }
+}
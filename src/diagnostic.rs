@@ -0,0 +1,131 @@
+use std::fmt;
+
+/// Location of a piece of source code: byte range plus the 1-based line and
+/// column where it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+impl Span {
+    pub fn to(&self, other: Span) -> Span {
+        Span {
+            start: self.start,
+            end: other.end.max(self.end),
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+/// A named piece of source text diagnostics are rendered against.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub name: String,
+    pub text: String,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    /// Reported without stopping compilation.
+    Warning,
+}
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+        }
+    }
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.into(),
+            span,
+        }
+    }
+    /// Renders the diagnostic rustc-style: header, location, the offending
+    /// source line and a caret underline below the span.
+    pub fn render(&self, source: &Source) -> String {
+        let (file, source) = (source.name.as_str(), source.text.as_str());
+        let line_text = source.lines().nth(self.span.line.saturating_sub(1)).unwrap_or("");
+        let gutter = " ".repeat(self.span.line.to_string().len());
+        let line_start = source[..self.span.start.min(source.len())]
+            .rfind('\n')
+            .map_or(0, |i| i + 1);
+        let line_end = line_start + line_text.len();
+        let underline_end = self.span.end.clamp(self.span.start, line_end);
+        let width = source
+            .get(self.span.start..underline_end)
+            .map_or(1, |s| s.chars().count())
+            .max(1);
+        format!(
+            "{}: {}\n{gutter}--> {}:{}\n{gutter} |\n{} | {}\n{gutter} | {}{}",
+            self.severity,
+            self.message,
+            file,
+            self.span,
+            self.span.line,
+            line_text,
+            line_text
+                .chars()
+                .take(self.span.column.saturating_sub(1))
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect::<String>(),
+            "^".repeat(width),
+        )
+    }
+}
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} at {}", self.severity, self.message, self.span)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(text: &str) -> Source {
+        Source { name: "t.fq".to_string(), text: text.to_string() }
+    }
+    #[test]
+    fn underlines_a_span_on_one_line() {
+        let span = Span { start: 6, end: 8, line: 1, column: 7 };
+        let rendered = Diagnostic::error("unexpected token", span).render(&source("# x 1 +* 2\n~- x\n"));
+        assert_eq!(rendered, "error: unexpected token\n --> t.fq:1:7\n  |\n1 | # x 1 +* 2\n  |       ^^");
+    }
+    #[test]
+    fn underlines_one_column_at_the_end_of_the_file() {
+        let span = Span { start: 9, end: 9, line: 1, column: 10 };
+        let rendered = Diagnostic::error("unclosed bracket", span).render(&source("~- (1 + 2"));
+        assert_eq!(rendered, "error: unclosed bracket\n --> t.fq:1:10\n  |\n1 | ~- (1 + 2\n  |          ^");
+    }
+    #[test]
+    fn stops_the_underline_at_the_end_of_the_line() {
+        let span = Span { start: 12, end: 18, line: 10, column: 4 };
+        let text = "\n".repeat(9) + "~- (1,\n2)\n";
+        let rendered = Diagnostic::warning("long span", span).render(&source(&text));
+        assert_eq!(rendered, "warning: long span\n  --> t.fq:10:4\n   |\n10 | ~- (1,\n   |    ^^^");
+    }
+}
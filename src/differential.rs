@@ -0,0 +1,197 @@
+//! Runs every program through both the tree-walking interpreter and the VM
+//! and reports where they disagree on what was printed, what the program
+//! evaluated to, or what it left in its top-level frame. What was printed
+//! must also match the program's `.out` file, which ends with the
+//! `runtime error:` line if the program is meant to fail, and the lowered
+//! program must survive a trip through its textual listing. With `-O`
+//! the VM runs the folded tree, rewritten by `peephole` once lowered, while
+//! the interpreter keeps the original, and the instruction counts with and
+//! without are reported.
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+use crate::diagnostic::{Diagnostic, Source};
+use crate::fold::fold;
+use crate::inout::Vocabulary;
+use crate::interp::Interpreter;
+use crate::ir::{assemble, ast_to_ir, disassemble, peephole, Program, VerifyError};
+use crate::lexer::tokenize_code;
+use crate::parser::parse_program;
+use crate::types::{FlowListener, Statement};
+use crate::vm::{self, ErrorKind, Options, Runtime, RuntimeError, Scope, VarV};
+
+/// What checking one program found.
+struct Report {
+    differences: Vec<String>,
+    /// Instructions without and with `-O`, if it was given.
+    instructions: Option<(usize, usize)>,
+}
+/// What one path made of a program.
+struct Run {
+    output: Vec<String>,
+    result: Result<VarV, ErrorKind>,
+    heap: Scope,
+}
+/// Checks every `.fq` file in `dir`, printing one line per file; the
+/// benchmarks in `code/bench` and other subdirectories are left out.
+/// Returns whether every file could be run and both paths gave its
+/// expected output.
+pub fn check_all(dir: &str, vocabulary: &Vocabulary, options: Options, optimize: bool) -> bool {
+    let mut names: Vec<String> = fs::read_dir(dir)
+        .expect("cannot read code directory")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".fq"))
+        .collect();
+    names.sort();
+    let mut agreed = true;
+    for name in names {
+        let text = fs::read_to_string(&name).expect("cannot read file");
+        let source = Source { name, text };
+        match check(&source, vocabulary, options, optimize) {
+            Err(reason) => {
+                agreed = false;
+                println!("FAILED   {}: {}", source.name, reason);
+            }
+            Ok(report) if report.differences.is_empty() => match report.instructions {
+                Some((before, after)) => println!("ok       {} ({} -> {} instructions)", source.name, before, after),
+                None => println!("ok       {}", source.name),
+            },
+            Ok(report) => {
+                agreed = false;
+                println!("MISMATCH {}", source.name);
+                for difference in report.differences {
+                    println!("  {}", difference);
+                }
+            }
+        }
+    }
+    agreed
+}
+/// Where the two runs of a program differ, or why it could not be run.
+fn check(source: &Source, vocabulary: &Vocabulary, options: Options, optimize: bool) -> Result<Report, String> {
+    let expected_name = format!("{}.out", source.name.trim_end_matches(".fq"));
+    let expected = fs::read_to_string(&expected_name).map_err(|_| format!("cannot read {}", expected_name))?;
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    let mut names: Vec<String> = Vec::new();
+    let tokens = tokenize_code(source.text.clone(), vocabulary, None, Some(&mut names), &mut diagnostics);
+    if !diagnostics.is_empty() {
+        return Err(format!("{} lexer error(s)", diagnostics.len()));
+    }
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let listener = RefCell::new(FlowListener::Capture(lines.clone()));
+    let tree = parse_program(&tokens, &mut diagnostics, &listener)
+        .map_err(|errors| format!("{} parse error(s)", errors.len()))?;
+    let Statement::Block(statements) = &tree else {
+        return Err("program is not a block".to_string());
+    };
+
+    let mut env: Vec<Scope> = vec![Scope::new()];
+    let result = Interpreter::new(options).execute(statements, &mut env);
+    let interpreted = finish(result, env, &lines);
+
+    let invalid = |error: VerifyError| format!("lowered to invalid code: {}", error);
+    let mut code = ast_to_ir(statements).map_err(invalid)?;
+    let mut instructions = None;
+    if optimize {
+        let mut statements = statements.clone();
+        statements.iter_mut().for_each(|statement| fold(statement, options));
+        let optimized = ast_to_ir(&statements).and_then(peephole).map_err(invalid)?;
+        instructions = Some((code.len(), optimized.len()));
+        code = optimized;
+    }
+    let program = Program { code, names };
+    let mut env: Vec<Scope> = vec![Scope::new()];
+    let result = vm::execute(&program.code, &mut env, &mut Runtime::new(options));
+    let compiled = finish(result, env, &lines);
+
+    let mut differences: Vec<String> = Vec::new();
+    let printed = transcript(&interpreted, &program.names);
+    if printed != expected {
+        differences.push(format!("expected {:?}, interpreter {:?}", expected, printed));
+    }
+    if interpreted.output != compiled.output {
+        differences.push(format!("output: interpreter {:?}, vm {:?}", interpreted.output, compiled.output));
+    }
+    if interpreted.result != compiled.result {
+        differences.push(format!("result: interpreter {:?}, vm {:?}", interpreted.result, compiled.result));
+    }
+    if interpreted.heap != compiled.heap {
+        differences.push(format!("heap:   interpreter {:?}, vm {:?}", interpreted.heap, compiled.heap));
+    }
+    let listing = disassemble(&program);
+    match assemble(&listing) {
+        Ok(program) if disassemble(&program) == listing => (),
+        Ok(_) => differences.push("listing: assembles to a different program".to_string()),
+        Err(error) => differences.push(format!("listing: {}", error)),
+    }
+    Ok(Report { differences, instructions })
+}
+/// What a run printed as its `.out` file holds it.
+fn transcript(run: &Run, names: &[String]) -> String {
+    let mut text: String = run.output.iter().map(|line| format!("{}\n", line)).collect();
+    if let Err(kind) = &run.result {
+        text.push_str(&format!("{}\n", RuntimeError::from(kind.clone()).named(names)));
+    }
+    text
+}
+fn finish(result: Result<VarV, vm::RuntimeError>, mut env: Vec<Scope>, lines: &RefCell<Vec<String>>) -> Run {
+    Run {
+        output: std::mem::take(&mut *lines.borrow_mut()),
+        result: result.map_err(|error| error.kind),
+        heap: env.drain(..).next().unwrap_or_default(),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inout::read_json;
+
+    /// Runs the samples in `code/` as `--differential` does.
+    fn check_samples(optimize: bool) -> bool {
+        let vocabulary: Vocabulary = read_json("vocabulary.json".to_string());
+        std::thread::Builder::new()
+            .stack_size(crate::VM_STACK_SIZE)
+            .spawn(move || check_all("code", &vocabulary, Options::default(), optimize))
+            .expect("cannot start check thread")
+            .join()
+            .expect("check thread panicked")
+    }
+    /// What `text` prints on the interpreter and on the VM.
+    fn outputs(text: &str) -> (Vec<String>, Vec<String>) {
+        let vocabulary: Vocabulary = read_json("vocabulary.json".to_string());
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        let tokens = tokenize_code(text.to_string(), &vocabulary, None, Some(&mut Vec::new()), &mut diagnostics);
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        let listener = RefCell::new(FlowListener::Capture(lines.clone()));
+        let Ok(Statement::Block(statements)) = parse_program(&tokens, &mut diagnostics, &listener) else {
+            panic!("cannot parse {:?}", text);
+        };
+        let interpreted = Interpreter::new(Options::default()).execute(&statements, &mut vec![Scope::new()]);
+        let interpreted = finish(interpreted, Vec::new(), &lines).output;
+        let code = ast_to_ir(&statements).expect("test program lowers to invalid code");
+        let compiled = vm::execute(&code, &mut vec![Scope::new()], &mut Runtime::new(Options::default()));
+        (interpreted, finish(compiled, Vec::new(), &lines).output)
+    }
+    #[test]
+    fn short_circuit_skips_the_right_side() {
+        let text = "fn noisy() { ~- \"ran\", return == }
+            ~- !! & noisy(),
+            ~- == | noisy(),
+            ~- == & noisy(),
+            ~- !! | noisy()";
+        let expected = ["false", "true", "ran", "true", "ran", "true"];
+        let (interpreted, compiled) = outputs(text);
+        assert_eq!(interpreted, expected);
+        assert_eq!(compiled, expected);
+    }
+    #[test]
+    fn samples_give_their_expected_output() {
+        assert!(check_samples(false));
+    }
+    #[test]
+    fn optimized_samples_give_their_expected_output() {
+        assert!(check_samples(true));
+    }
+}
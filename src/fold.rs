@@ -0,0 +1,244 @@
+//! Constant folding over the `Statement` tree, run before lowering when
+//! `-O` is given. Operators on literals are evaluated with the VM's own
+//! operations under the program's overflow policy; one that would fail is
+//! kept, so the run still reports it. An `if` on a literal keeps only the
+//! branch it takes.
+//!
+//! `x * 1`, `1 * x`, `x / 1` and `x - 0` become `x` only where `x` is known
+//! to be a number, and `x + 0` and `0 + x` only where it is an integer: on
+//! strings and booleans these mean something else or fail, and a float
+//! `-0.0 + 0` is `0.0`. A variable is known to be a number if every
+//! assignment to its name gives one; names also bound some other way, as a
+//! parameter, a loop variable or in a pattern, are not.
+use std::collections::HashMap;
+use std::iter::once;
+
+use crate::ir::IR;
+use crate::types::{ActionV, ComparsionV, Pattern, Statement};
+use crate::vm::{do_operation, Options, VarV};
+
+/// What is known about the type of an expression's value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Integer,
+    Number,
+}
+/// The kinds of the variables known to hold numbers, by name.
+type Variables = HashMap<usize, Kind>;
+/// Folds `statement` and everything below it in place.
+pub fn fold(statement: &mut Statement, options: Options) {
+    let variables = variables(statement);
+    rewrite(statement, options, &variables);
+}
+fn rewrite(statement: &mut Statement, options: Options, variables: &Variables) {
+    for child in children(statement) {
+        rewrite(child, options, variables);
+    }
+    if let Statement::Block(statements) = statement {
+        // what is left of an `if` whose branch was dropped
+        statements.retain(|statement| !matches!(unspanned(statement), Statement::Nil));
+    }
+    if let Some(folded) = simplify(statement, options, variables) {
+        *statement = folded;
+    }
+}
+/// Starts from every assigned name holding integers and weakens that until
+/// it agrees with every assignment, so `# n n + 1` keeps `n` an integer.
+fn variables(statement: &mut Statement) -> Variables {
+    let mut assigned: Vec<(usize, Statement)> = Vec::new();
+    let mut bound: Vec<usize> = Vec::new();
+    assignments(statement, &mut assigned, &mut bound);
+    let mut kinds: HashMap<usize, Option<Kind>> = assigned.iter().map(|(name, _)| (*name, Some(Kind::Integer))).collect();
+    for name in bound {
+        kinds.insert(name, None);
+    }
+    loop {
+        let mut changed = false;
+        for (name, value) in &assigned {
+            let known = kinds[name];
+            let variables: Variables = kinds.iter().filter_map(|(name, kind)| Some((*name, (*kind)?))).collect();
+            let joined = match (known, kind(value, &variables)) {
+                (Some(Kind::Integer), Some(Kind::Integer)) => Some(Kind::Integer),
+                (Some(_), Some(_)) => Some(Kind::Number),
+                _ => None,
+            };
+            if joined != known {
+                kinds.insert(*name, joined);
+                changed = true;
+            }
+        }
+        if !changed {
+            return kinds.into_iter().filter_map(|(name, kind)| Some((name, kind?))).collect();
+        }
+    }
+}
+/// Collects the value of every `Set` by name into `assigned`, and into
+/// `bound` the names given values any other way.
+fn assignments(statement: &mut Statement, assigned: &mut Vec<(usize, Statement)>, bound: &mut Vec<usize>) {
+    match statement {
+        Statement::Set { var, value } => assigned.push((var.name, value.as_ref().clone())),
+        Statement::SetIndex { var, .. } | Statement::For { var, .. } => bound.push(var.name),
+        Statement::Function { params, .. } => bound.extend(params.iter().copied()),
+        Statement::Match { arms, .. } => {
+            let patterns = arms.iter().flat_map(|arm| &arm.patterns);
+            bound.extend(patterns.flat_map(Pattern::bindings).map(|var| var.name));
+        }
+        _ => (),
+    }
+    for child in children(statement) {
+        assignments(child, assigned, bound);
+    }
+}
+fn children(statement: &mut Statement) -> Vec<&mut Statement> {
+    match statement {
+        Statement::Block(statements) | Statement::List(statements) | Statement::Call { args: statements, .. } => {
+            statements.iter_mut().collect()
+        }
+        Statement::Set { value: statement, .. }
+        | Statement::Out { expr: statement, .. }
+        | Statement::Function { body: statement, .. }
+        | Statement::Length(statement)
+        | Statement::Spanned(_, statement) => vec![statement.as_mut()],
+        Statement::Comparsion(_, left, right)
+        | Statement::OperationNumder(_, left, right)
+        | Statement::Range(left, right)
+        | Statement::Index { list: left, index: right } => vec![left.as_mut(), right.as_mut()],
+        Statement::OperationBool(_, left, right) => once(left.as_mut()).chain(right.as_deref_mut()).collect(),
+        Statement::If(condition, then, otherwise) => {
+            [condition.as_mut(), then.as_mut()].into_iter().chain(otherwise.as_deref_mut()).collect()
+        }
+        Statement::Return(value) => value.as_deref_mut().into_iter().collect(),
+        Statement::Slice { list, start, end } => {
+            once(list.as_mut()).chain(start.as_deref_mut()).chain(end.as_deref_mut()).collect()
+        }
+        Statement::SetIndex { indices, value, .. } => indices.iter_mut().chain(once(value.as_mut())).collect(),
+        // patterns are literals already
+        Statement::Match { subject, arms, .. } => subject
+            .iter_mut()
+            .chain(arms.iter_mut().flat_map(|arm| arm.guard.iter_mut().chain(once(&mut arm.body))))
+            .collect(),
+        Statement::While { condition, body } => once(condition.as_mut()).chain(body.iter_mut()).collect(),
+        Statement::For { iterable, body, .. } => once(iterable.as_mut()).chain(body.iter_mut()).collect(),
+        Statement::Nil
+        | Statement::Name(_)
+        | Statement::Bool(_)
+        | Statement::Number(_)
+        | Statement::Float(_)
+        | Statement::Str(_)
+        | Statement::In(_)
+        | Statement::Jump { .. } => Vec::new(),
+        #[cfg(feature = "bigint")]
+        Statement::BigNumber(_) => Vec::new(),
+    }
+}
+/// What `statement` becomes, if anything, once its operands are folded.
+fn simplify(statement: &mut Statement, options: Options, variables: &Variables) -> Option<Statement> {
+    match statement {
+        Statement::OperationNumder(action, left, right) => match (literal(left), literal(right)) {
+            (Some(left), Some(right)) => evaluate(vec![left, right], &IR::BinExpr(action.clone()), options),
+            _ => identity(action, left, right, variables),
+        },
+        Statement::Comparsion(comparsion, left, right) => {
+            let operation = match comparsion {
+                ComparsionV::Equal => IR::Eql,
+                ComparsionV::NotEqual => IR::NEql,
+                ComparsionV::Less => IR::Ls,
+                ComparsionV::Greater => IR::Gt,
+                ComparsionV::LessOrEqual => IR::LsEql,
+                ComparsionV::GreaterOrEqual => IR::GtEql,
+            };
+            evaluate(vec![literal(left)?, literal(right)?], &operation, options)
+        }
+        Statement::OperationBool(ActionV::Not, operand, None) => evaluate(vec![literal(operand)?], &IR::Not, options),
+        // short-circuiting as in `interp`: a deciding left side is the
+        // result, otherwise the right side is checked against `true`
+        Statement::OperationBool(action @ (ActionV::And | ActionV::Or), left, Some(right)) => {
+            let is_or = *action == ActionV::Or;
+            match literal(left)? {
+                VarV::Bool(b) if b == is_or => Some(Statement::Bool(b)),
+                VarV::Bool(_) => {
+                    let operation = if is_or { IR::Or } else { IR::And };
+                    evaluate(vec![literal(right)?, VarV::Bool(!is_or)], &operation, options)
+                }
+                _ => None,
+            }
+        }
+        Statement::If(condition, then, otherwise) => {
+            let taken = if literal(condition)? != VarV::Bool(false) { Some(then) } else { otherwise.as_mut() };
+            Some(taken.map_or(Statement::Nil, |branch| std::mem::replace(branch.as_mut(), Statement::Nil)))
+        }
+        _ => None,
+    }
+}
+/// The operand `action` leaves unchanged, if one side is its identity.
+fn identity(action: &ActionV, left: &mut Statement, right: &mut Statement, variables: &Variables) -> Option<Statement> {
+    let (left_n, right_n) = (integer(left), integer(right));
+    let (left_kind, right_kind) = (kind(left, variables), kind(right, variables));
+    let kept = match action {
+        ActionV::Mul if right_n == Some(1) && left_kind.is_some() => left,
+        ActionV::Mul if left_n == Some(1) && right_kind.is_some() => right,
+        ActionV::Div if right_n == Some(1) && left_kind.is_some() => left,
+        ActionV::Sub if right_n == Some(0) && left_kind.is_some() => left,
+        ActionV::Add if right_n == Some(0) && left_kind == Some(Kind::Integer) => left,
+        ActionV::Add if left_n == Some(0) && right_kind == Some(Kind::Integer) => right,
+        _ => return None,
+    };
+    Some(std::mem::replace(kept, Statement::Nil))
+}
+/// Runs `operation` on `operands` as the VM would, if it succeeds with a
+/// value a literal can hold.
+fn evaluate(mut operands: Vec<VarV>, operation: &IR, options: Options) -> Option<Statement> {
+    do_operation(&mut operands, operation, options.overflow).ok()?;
+    match operands.pop()? {
+        VarV::Num(v) => Some(Statement::Number(v)),
+        #[cfg(feature = "bigint")]
+        VarV::Big(v) => Some(Statement::BigNumber(v)),
+        VarV::Float(v) => Some(Statement::Float(v)),
+        VarV::Bool(v) => Some(Statement::Bool(v)),
+        VarV::Str(v) => Some(Statement::Str(v)),
+        VarV::Tuple(_) | VarV::List(_) => None,
+    }
+}
+/// The value of `statement` if it is a literal, maybe in parentheses.
+fn literal(statement: &Statement) -> Option<VarV> {
+    match unspanned(statement) {
+        Statement::Block(statements) if statements.len() == 1 => literal(&statements[0]),
+        Statement::Number(v) => Some(VarV::Num(*v)),
+        #[cfg(feature = "bigint")]
+        Statement::BigNumber(v) => Some(VarV::Big(v.clone())),
+        Statement::Float(v) => Some(VarV::Float(*v)),
+        Statement::Bool(v) => Some(VarV::Bool(*v)),
+        Statement::Str(v) => Some(VarV::Str(v.clone())),
+        _ => None,
+    }
+}
+/// The value of `statement` if it is an integer literal; unlike `literal`
+/// this tells `1` from `1.0`.
+fn integer(statement: &Statement) -> Option<isize> {
+    match literal(statement)? {
+        VarV::Num(v) => Some(v),
+        _ => None,
+    }
+}
+fn kind(statement: &Statement, variables: &Variables) -> Option<Kind> {
+    match unspanned(statement) {
+        Statement::Number(_) | Statement::Length(_) => Some(Kind::Integer),
+        #[cfg(feature = "bigint")]
+        Statement::BigNumber(_) => Some(Kind::Integer),
+        Statement::Float(_) => Some(Kind::Number),
+        Statement::Name(var) => variables.get(&var.name).copied(),
+        Statement::Block(statements) if statements.len() == 1 => kind(&statements[0], variables),
+        Statement::OperationNumder(_, left, right) => match (kind(left, variables)?, kind(right, variables)?) {
+            (Kind::Integer, Kind::Integer) => Some(Kind::Integer),
+            _ => Some(Kind::Number),
+        },
+        Statement::OperationBool(ActionV::Not, operand, None) => kind(operand, variables),
+        _ => None,
+    }
+}
+fn unspanned(statement: &Statement) -> &Statement {
+    match statement {
+        Statement::Spanned(_, statement) => unspanned(statement),
+        statement => statement,
+    }
+}
@@ -4,14 +4,29 @@ use std::{
     fs::File,
 };
 
-use crate::types::Statement;
+use crate::types::{Pattern, Statement};
 #[derive(Debug)]
 pub struct Vocabulary {
     pub keywords: HashMap<String, u8>,
+    pub comments: Comments,
+}
+/// Comment delimiters. Either kind can be left out to disable it, or, in a
+/// vocabulary with a parent, to keep the parent's.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Comments {
+    pub line: Option<String>,
+    pub block: Option<(String, String)>,
+}
+impl Comments {
+    /// These delimiters, with any left out taken from `parent`.
+    fn or(self, parent: Comments) -> Comments {
+        Comments { line: self.line.or(parent.line), block: self.block.or(parent.block) }
+    }
 }
 #[derive(Debug, Serialize, Deserialize)]
 struct VocabularyBuilder {
     keywords: HashMap<String, u8>,
+    comments: Option<Comments>,
     parent: Option<String>,
 }
 impl From<VocabularyBuilder> for Vocabulary {
@@ -19,6 +34,7 @@ impl From<VocabularyBuilder> for Vocabulary {
         match vb.parent {
             None => Vocabulary {
                 keywords: vb.keywords,
+                comments: vb.comments.unwrap_or_default(),
             },
             Some(p) => {
                 let parent = read_json(p + ".json");
@@ -26,7 +42,8 @@ impl From<VocabularyBuilder> for Vocabulary {
                 for (k, v) in vb.keywords {
                     keywords.insert(k, v);
                 }
-                Vocabulary {keywords}
+                let comments = vb.comments.unwrap_or_default().or(parent.comments);
+                Vocabulary {keywords, comments}
             }
         }
     }
@@ -41,6 +58,10 @@ pub fn print_tree(node: Statement, depth: usize) {
     let indent = "\t".repeat(depth);
     match node {
         Statement::Number(val) => println!("{}Number: {}", indent, val),
+        Statement::Float(val) => println!("{}Float: {:?}", indent, val),
+        #[cfg(feature = "bigint")]
+        Statement::BigNumber(val) => println!("{}Number: {}", indent, val),
+        Statement::Str(val) => println!("{}String: {:?}", indent, val),
         Statement::OperationNumder(op, left, right) => {
             println!("{}{:?}", indent, op);
             print_tree(*left, depth + 1);
@@ -49,9 +70,8 @@ pub fn print_tree(node: Statement, depth: usize) {
         Statement::OperationBool(op, left, right) => {
             println!("{}{:?}", indent, op);
             print_tree(*left, depth + 1);
-            match right {
-                Some(v) => print_tree(*v, depth + 1),
-                None => (),
+            if let Some(v) = right {
+                print_tree(*v, depth + 1);
             }
         }
         Statement::Bool(val) => println!("{}Boolean: {}", indent, val),
@@ -63,9 +83,8 @@ pub fn print_tree(node: Statement, depth: usize) {
             println!("{}Do:", indent);
             print_tree(*if_, depth + 1);
             println!("{}Else do: ", indent);
-            match else_ {
-                Some(v) => print_tree(*v, depth + 1),
-                None => (),
+            if let Some(v) = else_ {
+                print_tree(*v, depth + 1);
             }
         }
         Statement::Comparsion(comparsion_type, left, right) => {
@@ -75,7 +94,7 @@ pub fn print_tree(node: Statement, depth: usize) {
         }
         Statement::Block(vec) => {
             for stmt in vec {
-                print_tree(*stmt, depth);
+                print_tree(stmt, depth);
             }
         }
         Statement::Out { expr, to } => {
@@ -86,18 +105,308 @@ pub fn print_tree(node: Statement, depth: usize) {
         Statement::In(streamer) => {
             println!("{}Get from: {:?}", indent, streamer);
         }
-        Statement::Name(name) => println!("{}Name: {:?}", indent, name),
-        Statement::Jump(up) => {
-            let place = if up {
+        Statement::Name(var) => println!("{}Name: {} at {}:{}", indent, var.name, var.depth, var.slot),
+        Statement::Jump { repeat, depth: levels } => {
+            let place = if repeat {
                 String::from("up")
             } else {
                 String::from("down")
             };
-            println!("{}Jump: {}", indent, place);
+            println!("{}Jump: {} {} blocks out", indent, place, levels);
+        }
+        Statement::Set { var, value } => {
+            println!("{}Set to {} at {}:{}:", indent, var.name, var.depth, var.slot);
+            print_tree(*value, depth + 1);
+        }
+        Statement::Function { name, params, body } => {
+            println!("{}Function {} with parameters {:?}:", indent, name, params);
+            print_tree(*body, depth + 1);
+        }
+        Statement::Call { name, args } => {
+            println!("{}Call {}:", indent, name);
+            for arg in args {
+                print_tree(arg, depth + 1);
+            }
+        }
+        Statement::Return(value) => {
+            println!("{}Return from function:", indent);
+            if let Some(v) = value {
+                print_tree(*v, depth + 1);
+            }
         }
-        Statement::Set { name, value } => {
-            println!("{}Set to {}:", indent, name);
+        Statement::List(items) => {
+            println!("{}List:", indent);
+            for item in items {
+                print_tree(item, depth + 1);
+            }
+        }
+        Statement::Index { list, index } => {
+            println!("{}Index:", indent);
+            print_tree(*list, depth + 1);
+            print_tree(*index, depth + 1);
+        }
+        Statement::Slice { list, start, end } => {
+            println!("{}Slice:", indent);
+            print_tree(*list, depth + 1);
+            println!("{}From:", indent);
+            if let Some(v) = start {
+                print_tree(*v, depth + 1);
+            }
+            println!("{}To:", indent);
+            if let Some(v) = end {
+                print_tree(*v, depth + 1);
+            }
+        }
+        Statement::Length(value) => {
+            println!("{}Length of:", indent);
+            print_tree(*value, depth + 1);
+        }
+        Statement::SetIndex { var, indices, value } => {
+            println!("{}Set element of {} at {}:{}:", indent, var.name, var.depth, var.slot);
+            for index in indices {
+                print_tree(index, depth + 1);
+            }
+            println!("{}To:", indent);
             print_tree(*value, depth + 1);
         }
+        Statement::Match { subject, arms, .. } => {
+            println!("{}Match:", indent);
+            for value in subject {
+                print_tree(value, depth + 1);
+            }
+            for arm in arms {
+                println!("{}Arm {:?}:", indent, arm.patterns);
+                if let Some(guard) = arm.guard {
+                    println!("{}If:", indent);
+                    print_tree(guard, depth + 1);
+                }
+                println!("{}Do:", indent);
+                print_tree(arm.body, depth + 1);
+            }
+        }
+        Statement::Range(start, end) => {
+            println!("{}Range:", indent);
+            print_tree(*start, depth + 1);
+            print_tree(*end, depth + 1);
+        }
+        Statement::While { condition, body } => {
+            println!("{}While:", indent);
+            print_tree(*condition, depth + 1);
+            println!("{}Do:", indent);
+            for stmt in body {
+                print_tree(stmt, depth + 1);
+            }
+        }
+        Statement::For { var, iterable, body, .. } => {
+            println!("{}For {} at {}:{} in:", indent, var.name, var.depth, var.slot);
+            print_tree(*iterable, depth + 1);
+            println!("{}Do:", indent);
+            for stmt in body {
+                print_tree(stmt, depth + 1);
+            }
+        }
+        Statement::Spanned(_, stmt) => print_tree(*stmt, depth),
+    }
+}
+/// `text` as a quoted DOT string, each of its lines left-aligned.
+pub fn dot_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\l"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+/// The tree as a DOT digraph for `--tree`: a node per statement, with the
+/// edges to its parts labelled the way `print_tree` heads them.
+pub fn tree_dot(node: &Statement, names: &[String]) -> String {
+    let mut out = String::from("digraph tree {\n    node [shape=box];\n");
+    tree_node(node, names, &mut out, &mut 0);
+    out.push_str("}\n");
+    out
+}
+/// Writes `node` and everything below it, returning the node's number.
+fn tree_node(node: &Statement, names: &[String], out: &mut String, next: &mut usize) -> usize {
+    let name = |id: usize| match names.get(id) {
+        _ if id == usize::MAX => "$".to_string(),
+        Some(name) => name.clone(),
+        None => format!("#{}", id),
+    };
+    if let Statement::Spanned(_, stmt) = node {
+        return tree_node(stmt, names, out, next);
+    }
+    let id = *next;
+    *next += 1;
+    let mut parts: Vec<(&str, &Statement)> = Vec::new();
+    let label = match node {
+        Statement::Spanned(..) => unreachable!(),
+        Statement::Block(statements) => {
+            parts.extend(statements.iter().map(|stmt| ("", stmt)));
+            "Block".to_string()
+        }
+        Statement::Set { var, value } => {
+            parts.push(("value", value));
+            format!("Set {}", name(var.name))
+        }
+        Statement::Nil => "Nil".to_string(),
+        Statement::Name(var) => format!("Name: {}", name(var.name)),
+        Statement::Bool(val) => format!("Boolean: {}", val),
+        Statement::Number(val) => format!("Number: {}", val),
+        Statement::Float(val) => format!("Float: {:?}", val),
+        #[cfg(feature = "bigint")]
+        Statement::BigNumber(val) => format!("Number: {}", val),
+        Statement::Str(val) => format!("String: {:?}", val),
+        Statement::Comparsion(comparsion, left, right) => {
+            parts.extend([("", left.as_ref()), ("", right.as_ref())]);
+            format!("{:?}", comparsion)
+        }
+        Statement::OperationBool(op, left, right) => {
+            parts.push(("", left));
+            parts.extend(right.as_deref().map(|right| ("", right)));
+            format!("{:?}", op)
+        }
+        Statement::OperationNumder(op, left, right) => {
+            parts.extend([("", left.as_ref()), ("", right.as_ref())]);
+            format!("{:?}", op)
+        }
+        Statement::If(condition, if_, else_) => {
+            parts.extend([("if", condition.as_ref()), ("do", if_.as_ref())]);
+            parts.extend(else_.as_deref().map(|else_| ("else do", else_)));
+            "If".to_string()
+        }
+        Statement::Out { expr, .. } => {
+            parts.push(("", expr));
+            "Output".to_string()
+        }
+        Statement::In(streamer) => format!("Get from: {:?}", streamer.borrow()),
+        Statement::Jump { repeat, depth } => {
+            format!("Jump: {} {} blocks out", if *repeat { "up" } else { "down" }, depth)
+        }
+        Statement::Function { name: function, params, body } => {
+            parts.push(("", body));
+            let params: Vec<String> = params.iter().map(|param| name(*param)).collect();
+            format!("Function {}({})", name(*function), params.join(", "))
+        }
+        Statement::Call { name: function, args } => {
+            parts.extend(args.iter().map(|arg| ("", arg)));
+            format!("Call {}", name(*function))
+        }
+        Statement::Return(value) => {
+            parts.extend(value.as_deref().map(|value| ("", value)));
+            "Return from function".to_string()
+        }
+        Statement::List(items) => {
+            parts.extend(items.iter().map(|item| ("", item)));
+            "List".to_string()
+        }
+        Statement::Index { list, index } => {
+            parts.extend([("", list.as_ref()), ("index", index.as_ref())]);
+            "Index".to_string()
+        }
+        Statement::Slice { list, start, end } => {
+            parts.push(("", list));
+            parts.extend(start.as_deref().map(|start| ("from", start)));
+            parts.extend(end.as_deref().map(|end| ("to", end)));
+            "Slice".to_string()
+        }
+        Statement::Length(value) => {
+            parts.push(("", value));
+            "Length of".to_string()
+        }
+        Statement::SetIndex { var, indices, value } => {
+            parts.extend(indices.iter().map(|index| ("index", index)));
+            parts.push(("to", value));
+            format!("Set element of {}", name(var.name))
+        }
+        Statement::Match { subject, arms, .. } => {
+            out.push_str(&format!("    n{} [label=\"Match\"];\n", id));
+            for value in subject {
+                let child = tree_node(value, names, out, next);
+                out.push_str(&format!("    n{} -> n{};\n", id, child));
+            }
+            // arms are not statements, so they get nodes of their own here
+            for arm in arms {
+                let arm_id = *next;
+                *next += 1;
+                let patterns: Vec<String> = arm.patterns.iter().map(|pattern| pattern_text(pattern, &name)).collect();
+                let label = dot_string(&format!("Arm {}", patterns.join(", ")));
+                out.push_str(&format!("    n{} [label={}, shape=oval];\n", arm_id, label));
+                out.push_str(&format!("    n{} -> n{};\n", id, arm_id));
+                let guard = arm.guard.iter().map(|guard| ("if", guard));
+                for (part, stmt) in guard.chain([("do", &arm.body)]) {
+                    let child = tree_node(stmt, names, out, next);
+                    out.push_str(&format!("    n{} -> n{} [label=\"{}\"];\n", arm_id, child, part));
+                }
+            }
+            return id;
+        }
+        Statement::Range(start, end) => {
+            parts.extend([("", start.as_ref()), ("", end.as_ref())]);
+            "Range".to_string()
+        }
+        Statement::While { condition, body } => {
+            parts.push(("while", condition));
+            parts.extend(body.iter().map(|stmt| ("do", stmt)));
+            "While".to_string()
+        }
+        Statement::For { var, iterable, body, .. } => {
+            parts.push(("in", iterable));
+            parts.extend(body.iter().map(|stmt| ("do", stmt)));
+            format!("For {}", name(var.name))
+        }
+    };
+    out.push_str(&format!("    n{} [label={}];\n", id, dot_string(&label)));
+    for (part, stmt) in parts {
+        let child = tree_node(stmt, names, out, next);
+        match part {
+            "" => out.push_str(&format!("    n{} -> n{};\n", id, child)),
+            part => out.push_str(&format!("    n{} -> n{} [label=\"{}\"];\n", id, child, part)),
+        }
+    }
+    id
+}
+/// A pattern the way it is written in a program.
+fn pattern_text(pattern: &Pattern, name: &dyn Fn(usize) -> String) -> String {
+    match pattern {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Bind(var) => name(var.name),
+        Pattern::Value(value) => literal_text(value),
+        Pattern::Tuple(patterns) => {
+            let patterns: Vec<String> = patterns.iter().map(|pattern| pattern_text(pattern, name)).collect();
+            format!("({})", patterns.join(", "))
+        }
+    }
+}
+fn literal_text(value: &Statement) -> String {
+    match value {
+        Statement::Spanned(_, value) => literal_text(value),
+        Statement::Number(val) => val.to_string(),
+        #[cfg(feature = "bigint")]
+        Statement::BigNumber(val) => val.to_string(),
+        Statement::Float(val) => format!("{:?}", val),
+        Statement::Str(val) => format!("{:?}", val),
+        Statement::Bool(val) => val.to_string(),
+        _ => unreachable!("the parser only accepts literals as patterns"),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vocabulary(json: &str) -> Vocabulary {
+        Vocabulary::from(serde_json::from_str::<VocabularyBuilder>(json).expect("test vocabulary does not parse"))
+    }
+    #[test]
+    fn inherits_each_comment_delimiter() {
+        let child = vocabulary(r#"{"keywords": {}, "parent": "std", "comments": {"line": "--"}}"#);
+        let block = Some(("/*".to_string(), "*/".to_string()));
+        assert_eq!(child.comments, Comments { line: Some("--".to_string()), block: block.clone() });
+        let child = vocabulary(r#"{"keywords": {}, "parent": "std"}"#);
+        assert_eq!(child.comments, Comments { line: Some("//".to_string()), block });
     }
 }
@@ -0,0 +1,377 @@
+//! Reference semantics: runs a `Statement` tree directly instead of lowering
+//! it with `ir::ast_to_ir`. Values and operators are shared with the VM,
+//! control flow is implemented independently, so the two agreeing on a
+//! program is evidence the lowering is right.
+use std::collections::HashMap;
+
+use crate::ir::IR;
+use crate::types::{ActionV, ComparsionV, MatchArm, Pattern, Statement, Var};
+use crate::vm::{do_operation, load, pack, pop, store, unpack};
+use crate::vm::{ErrorKind, Options, RuntimeError, Scope, VarV, MAX_CALL_DEPTH};
+
+/// Evaluates an operand, passing jumps and returns that escape from a block
+/// inside it on to the enclosing statement, as the VM does.
+macro_rules! operand {
+    ($this:expr, $statement:expr, $stack:expr, $env:expr) => {
+        match $this.eval($statement, $stack, $env)? {
+            Flow::Next => (),
+            flow => return Ok(flow),
+        }
+    };
+}
+/// How evaluating a statement ended, as seen by the enclosing block.
+enum Flow {
+    Next,
+    /// `-> |+` or `-> |-` aimed at the block `depth` levels out.
+    Jump { repeat: bool, depth: usize },
+    Return(VarV),
+}
+pub struct Interpreter<'a> {
+    pub options: Options,
+    functions: HashMap<usize, (usize, &'a Statement)>,
+    depth: usize,
+}
+impl<'a> Interpreter<'a> {
+    pub fn new(options: Options) -> Self {
+        Interpreter { options, functions: HashMap::new(), depth: 0 }
+    }
+    /// Runs `statements` in the innermost frame of `env`, the way
+    /// `vm::execute` runs an instruction list.
+    pub fn execute(&mut self, statements: &'a [Statement], env: &mut Vec<Scope>) -> Result<VarV, RuntimeError> {
+        let mut stack: Vec<VarV> = Vec::new();
+        match self.repeat(&mut stack, env, |this, stack, env| this.run(statements, stack, env))? {
+            Flow::Return(value) => Ok(value),
+            _ => Ok(pack(stack)),
+        }
+    }
+    fn run(&mut self, statements: &'a [Statement], stack: &mut Vec<VarV>, env: &mut Vec<Scope>) -> Result<Flow, RuntimeError> {
+        for statement in statements {
+            match self.eval(statement, stack, env)? {
+                Flow::Next => (),
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+    /// Runs `body` again for as long as it restarts itself with `-> |+`;
+    /// jumps aimed further out lose one level of depth on the way.
+    fn repeat<F>(&mut self, stack: &mut Vec<VarV>, env: &mut Vec<Scope>, mut body: F) -> Result<Flow, RuntimeError>
+    where
+        F: FnMut(&mut Self, &mut Vec<VarV>, &mut Vec<Scope>) -> Result<Flow, RuntimeError>,
+    {
+        loop {
+            match body(self, stack, env)? {
+                Flow::Jump { repeat: true, depth: 0 } => continue,
+                Flow::Next | Flow::Jump { repeat: false, depth: 0 } => return Ok(Flow::Next),
+                Flow::Jump { repeat, depth } => return Ok(Flow::Jump { repeat, depth: depth - 1 }),
+                flow @ Flow::Return(_) => return Ok(flow),
+            }
+        }
+    }
+    /// `repeat` in a frame of its own, leaving what the body left on its
+    /// stack, like `IR::Enter` and `IR::Leave`.
+    fn block<F>(&mut self, stack: &mut Vec<VarV>, env: &mut Vec<Scope>, body: F) -> Result<Flow, RuntimeError>
+    where
+        F: FnMut(&mut Self, &mut Vec<VarV>, &mut Vec<Scope>) -> Result<Flow, RuntimeError>,
+    {
+        env.push(Scope::new());
+        let mut inner: Vec<VarV> = Vec::new();
+        let flow = self.repeat(&mut inner, env, body);
+        env.pop();
+        if let Ok(Flow::Next) = flow {
+            stack.append(&mut unpack(pack(inner)));
+        }
+        flow
+    }
+    fn eval(&mut self, statement: &'a Statement, stack: &mut Vec<VarV>, env: &mut Vec<Scope>) -> Result<Flow, RuntimeError> {
+        let overflow = self.options.overflow;
+        match statement {
+            Statement::Block(statements) => return self.block(stack, env, |this, stack, env| this.run(statements, stack, env)),
+            Statement::Set { var, value } => {
+                operand!(self, value, stack, env);
+                store(env, *var, pop(stack)?)?;
+            }
+            Statement::Nil => (),
+            Statement::Name(var) => stack.push(load(env, *var)?),
+            Statement::Bool(v) => stack.push(VarV::Bool(*v)),
+            Statement::Number(v) => stack.push(VarV::Num(*v)),
+            Statement::Float(v) => stack.push(VarV::Float(*v)),
+            #[cfg(feature = "bigint")]
+            Statement::BigNumber(v) => stack.push(VarV::Big(v.clone())),
+            Statement::Str(v) => stack.push(VarV::Str(v.clone())),
+            Statement::Comparsion(comparsion, left, right) => {
+                operand!(self, left, stack, env);
+                operand!(self, right, stack, env);
+                let operation = match comparsion {
+                    ComparsionV::Equal => IR::Eql,
+                    ComparsionV::NotEqual => IR::NEql,
+                    ComparsionV::Less => IR::Ls,
+                    ComparsionV::Greater => IR::Gt,
+                    ComparsionV::LessOrEqual => IR::LsEql,
+                    ComparsionV::GreaterOrEqual => IR::GtEql,
+                };
+                do_operation(stack, &operation, overflow)?;
+            }
+            Statement::OperationBool(action @ (ActionV::And | ActionV::Or), left, Some(right)) => {
+                let is_or = *action == ActionV::Or;
+                operand!(self, left, stack, env);
+                match stack.last() {
+                    Some(VarV::Bool(b)) if *b == is_or => return Ok(Flow::Next),
+                    Some(VarV::Bool(_)) => {
+                        stack.pop();
+                    }
+                    Some(value) => {
+                        return Err(ErrorKind::TypeMismatch { action: action.clone(), left: value.type_name(), right: None }.into());
+                    }
+                    None => return Err(ErrorKind::StackUnderflow.into()),
+                }
+                operand!(self, right, stack, env);
+                stack.push(VarV::Bool(!is_or));
+                do_operation(stack, &if is_or { IR::Or } else { IR::And }, overflow)?;
+            }
+            Statement::OperationBool(action, left, right) => {
+                operand!(self, left, stack, env);
+                if let Some(right) = right {
+                    operand!(self, right, stack, env);
+                }
+                let operation = match action {
+                    ActionV::Not => IR::Not,
+                    ActionV::And => IR::And,
+                    ActionV::Or => IR::Or,
+                    _ => return Ok(Flow::Next),
+                };
+                do_operation(stack, &operation, overflow)?;
+            }
+            Statement::OperationNumder(action, left, right) => {
+                operand!(self, left, stack, env);
+                operand!(self, right, stack, env);
+                do_operation(stack, &IR::BinExpr(action.clone()), overflow)?;
+            }
+            Statement::If(condition, then, otherwise) => {
+                operand!(self, condition, stack, env);
+                if pop(stack)? != VarV::Bool(false) {
+                    return self.eval(then, stack, env);
+                } else if let Some(otherwise) = otherwise {
+                    return self.eval(otherwise, stack, env);
+                }
+            }
+            Statement::Out { expr, to } => {
+                operand!(self, expr, stack, env);
+                let top = pop(stack)?;
+                assert!(to.borrow().get(top));
+            }
+            Statement::In(streamer) => stack.push(streamer.borrow().send()),
+            Statement::Jump { repeat, depth } => return Ok(Flow::Jump { repeat: *repeat, depth: *depth }),
+            Statement::Function { name, params, body } => {
+                self.functions.insert(*name, (params.len(), body));
+            }
+            Statement::Call { name, args } => {
+                for arg in args {
+                    operand!(self, arg, stack, env);
+                }
+                let value = self.call(*name, args.len(), stack)?;
+                stack.push(value);
+            }
+            Statement::Return(value) => {
+                let value = match value {
+                    Some(value) => {
+                        operand!(self, value, stack, env);
+                        pop(stack)?
+                    }
+                    None => VarV::Tuple(Vec::new()),
+                };
+                return Ok(Flow::Return(value));
+            }
+            Statement::List(items) => {
+                for item in items {
+                    operand!(self, item, stack, env);
+                }
+                let items = split(stack, items.len())?;
+                stack.push(VarV::List(items));
+            }
+            Statement::Index { list, index } => {
+                operand!(self, list, stack, env);
+                operand!(self, index, stack, env);
+                let index = pop(stack)?;
+                let value = pop(stack)?;
+                stack.push(value.index(&index)?);
+            }
+            Statement::Slice { list, start, end } => {
+                operand!(self, list, stack, env);
+                for bound in [start, end].into_iter().flatten() {
+                    operand!(self, bound, stack, env);
+                }
+                let end = if end.is_some() { Some(pop(stack)?) } else { None };
+                let start = if start.is_some() { Some(pop(stack)?) } else { None };
+                let value = pop(stack)?;
+                stack.push(value.slice(start.as_ref(), end.as_ref())?);
+            }
+            Statement::Length(value) => {
+                operand!(self, value, stack, env);
+                let value = pop(stack)?;
+                stack.push(value.len()?);
+            }
+            Statement::SetIndex { var, indices, value } => {
+                stack.push(load(env, *var)?);
+                for index in indices {
+                    operand!(self, index, stack, env);
+                }
+                operand!(self, value, stack, env);
+                let value = pop(stack)?;
+                let indices = split(stack, indices.len())?;
+                let list = pop(stack)?;
+                store(env, *var, list.set_item(&indices, value)?)?;
+            }
+            Statement::Match { subject, temps, arms } => {
+                return self.block(stack, env, |this, stack, env| this.run_match(subject, temps, arms, stack, env));
+            }
+            Statement::Range(start, end) => {
+                operand!(self, start, stack, env);
+                operand!(self, end, stack, env);
+                let end = pop(stack)?;
+                let start = pop(stack)?;
+                stack.push(start.range(&end)?);
+            }
+            Statement::While { condition, body } => {
+                return self.block(stack, env, |this, stack, env| {
+                    operand!(this, condition, stack, env);
+                    if pop(stack)? == VarV::Bool(false) {
+                        return Ok(Flow::Jump { repeat: false, depth: 0 });
+                    }
+                    match this.run(body, stack, env)? {
+                        Flow::Next => Ok(Flow::Jump { repeat: true, depth: 0 }),
+                        flow => Ok(flow),
+                    }
+                });
+            }
+            Statement::For { var, iterable, source, counter, body } => {
+                return self.block(stack, env, |this, stack, env| {
+                    let range = match iterable.as_ref() {
+                        Statement::Spanned(_, statement) => statement.as_ref(),
+                        statement => statement,
+                    };
+                    if let Statement::Range(start, end) = range {
+                        operand!(this, start, stack, env);
+                        store(env, *counter, pop(stack)?)?;
+                        operand!(this, end, stack, env);
+                        store(env, *source, pop(stack)?)?;
+                    } else {
+                        operand!(this, iterable, stack, env);
+                        store(env, *source, pop(stack)?)?;
+                        store(env, *counter, VarV::Num(0))?;
+                    }
+                    let is_range = matches!(range, Statement::Range(..));
+                    let (source, counter) = (outer(*source), outer(*counter));
+                    this.block(stack, env, |this, stack, env| {
+                        let (position, sequence) = (load(env, counter)?, load(env, source)?);
+                        let bound = if is_range { sequence.clone() } else { sequence.clone().len()? };
+                        stack.extend([position.clone(), bound]);
+                        do_operation(stack, &IR::Ls, overflow)?;
+                        if pop(stack)? == VarV::Bool(false) {
+                            return Ok(Flow::Jump { repeat: false, depth: 0 });
+                        }
+                        let item = if is_range { position.clone() } else { sequence.index(&position)? };
+                        store(env, *var, item)?;
+                        store(env, counter, position.apply(ActionV::Add, VarV::Num(1), overflow)?)?;
+                        match this.run(body, stack, env)? {
+                            Flow::Next => Ok(Flow::Jump { repeat: true, depth: 0 }),
+                            flow => Ok(flow),
+                        }
+                    })
+                });
+            }
+            Statement::Spanned(_, statement) => return self.eval(statement, stack, env),
+        }
+        Ok(Flow::Next)
+    }
+    fn run_match(
+        &mut self,
+        subject: &'a [Statement],
+        temps: &[Var],
+        arms: &'a [MatchArm],
+        stack: &mut Vec<VarV>,
+        env: &mut Vec<Scope>,
+    ) -> Result<Flow, RuntimeError> {
+        for value in subject {
+            operand!(self, value, stack, env);
+        }
+        for temp in temps.iter().rev() {
+            store(env, *temp, pop(stack)?)?;
+        }
+        for arm in arms {
+            let mut is_matching = true;
+            for (pattern, temp) in arm.patterns.iter().zip(temps) {
+                let value = load(env, *temp)?;
+                if !self.matches(pattern, value, env)? {
+                    is_matching = false;
+                    break;
+                }
+            }
+            if !is_matching {
+                continue;
+            }
+            if let Some(guard) = &arm.guard {
+                operand!(self, guard, stack, env);
+                if pop(stack)? == VarV::Bool(false) {
+                    continue;
+                }
+            }
+            return self.eval(&arm.body, stack, env);
+        }
+        Ok(Flow::Next)
+    }
+    /// Whether `value` matches `pattern`, storing the parts it binds on the
+    /// way.
+    fn matches(&mut self, pattern: &'a Pattern, value: VarV, env: &mut Vec<Scope>) -> Result<bool, RuntimeError> {
+        match (pattern, value) {
+            (Pattern::Wildcard, _) => Ok(true),
+            (Pattern::Bind(var), value) => {
+                store(env, *var, value)?;
+                Ok(true)
+            }
+            (Pattern::Value(literal), value) => {
+                let mut expected: Vec<VarV> = Vec::new();
+                self.eval(literal, &mut expected, env)?;
+                Ok(value == pack(expected))
+            }
+            (Pattern::Tuple(patterns), VarV::Tuple(items)) if patterns.len() == items.len() => {
+                for (pattern, item) in patterns.iter().zip(items) {
+                    if !self.matches(pattern, item, env)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            }
+            (Pattern::Tuple(_), _) => Ok(false),
+        }
+    }
+    fn call(&mut self, name: usize, argc: usize, stack: &mut Vec<VarV>) -> Result<VarV, RuntimeError> {
+        let (arity, body) = *self.functions.get(&name).ok_or(ErrorKind::UndefinedFunction(name))?;
+        if arity != argc {
+            return Err(ErrorKind::ArityMismatch { name, expected: arity, found: argc }.into());
+        }
+        if stack.len() < argc {
+            return Err(ErrorKind::StackUnderflow.into());
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(ErrorKind::CallDepthExceeded(MAX_CALL_DEPTH).into());
+        }
+        let args = split(stack, argc)?;
+        let mut env: Vec<Scope> = vec![args.into_iter().map(Some).collect()];
+        self.depth += 1;
+        let result = self.execute(std::slice::from_ref(body), &mut env);
+        self.depth -= 1;
+        result
+    }
+}
+/// The top `len` values of the stack, deepest first.
+fn split(stack: &mut Vec<VarV>, len: usize) -> Result<Vec<VarV>, RuntimeError> {
+    if stack.len() < len {
+        return Err(ErrorKind::StackUnderflow.into());
+    }
+    Ok(stack.split_off(stack.len() - len))
+}
+/// `var` as seen from a block nested one level deeper.
+fn outer(var: Var) -> Var {
+    Var { depth: var.depth + 1, ..var }
+}
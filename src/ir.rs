@@ -1,17 +1,51 @@
-use crate::types::{ActionV,ComparsionV, Statement};
+use crate::types::{ActionV,ComparsionV, Pattern, Statement, Var};
 use crate::types::{FlowListener, FlowStreamer};
+use crate::vm::VarV;
 use std::cell::RefCell;
+mod cfg;
+mod peephole;
+mod text;
+mod verify;
+pub use cfg::cfg_dot;
+pub use peephole::peephole;
+pub use text::{assemble, disassemble};
+pub(crate) use text::NameText;
+pub use verify::{verify, VerifyError};
+/// A lowered program with the source names of the ids it uses.
+#[derive(Debug)]
+pub struct Program {
+    pub code: Vec<IR>,
+    pub names: Vec<String>,
+}
 #[derive(Debug, Clone)]
-#[allow(unused_variables, dead_code)]
 pub enum MatchPattern {
-    Var(usize),
-    Val(Vec<IR>),
+    Var(Var),
+    /// A literal, evaluated once when the program is lowered.
+    Val(VarV),
     Unused,
+    /// A tuple with as many elements, each matching its pattern.
+    Tuple(Vec<MatchPattern>),
+}
+impl MatchPattern {
+    /// The variables the pattern stores to, nested ones included.
+    pub fn bindings(&self) -> Vec<Var> {
+        match self {
+            MatchPattern::Var(var) => vec![*var],
+            MatchPattern::Tuple(patterns) => patterns.iter().flat_map(MatchPattern::bindings).collect(),
+            MatchPattern::Val(_) | MatchPattern::Unused => Vec::new(),
+        }
+    }
 }
+/// One instruction of a flat program. Jump targets are instruction indices
+/// into the same program, filled in from labels by `Assembler::link`.
 #[derive(Debug, Clone)]
 pub enum IR {
     Num(isize),
+    Float(f64),
+    #[cfg(feature = "bigint")]
+    BigNum(num_bigint::BigInt),
     Bool(bool),
+    Str(String),
 
     Nil,
 
@@ -28,41 +62,204 @@ pub enum IR {
     LsEql,
     GtEql,
 
-    Store(usize),
-    Load(usize),
+    Store(Var),
+    Load(Var),
 
     Jump(usize),
-    Efine(Vec<IR>),
+    /// Jumps if the bool on top of the stack equals the flag, leaving it
+    /// there, and pops it otherwise.
+    Test(bool, usize),
+    /// Leaves the given number of blocks, dropping their frames and values,
+    /// and jumps to the start or end of the block it is then in.
+    Unwind(usize, usize),
+    /// Opens a block: a new environment frame with the given number of
+    /// slots and an empty value stack.
+    Enter(usize),
+    /// Closes the innermost block, moving its values onto the stack of the
+    /// enclosing one.
+    Leave,
+
+    /// Registers a function: name, number of parameters and the index of
+    /// its body.
+    Function(usize, usize, usize),
+    /// Calls a function with the given number of arguments from the stack.
+    Call(usize, usize),
+    /// Returns from the current function, with the top of the stack if set.
+    Ret(bool),
+    /// Ends a function body, returning everything on its stack.
+    End,
+
+    /// Collects the given number of values from the stack into a list.
+    List(usize),
+    Index,
+    /// Slices a list or string; the flags tell whether the start and end
+    /// bounds are on the stack.
+    Slice(bool, bool),
+    Len,
+    /// Pops the end and start and pushes the list of integers in between.
+    Range,
+    /// Replaces an element of a nested list: pops the value, the given
+    /// number of indices and the list, and pushes the updated list.
+    SetItem(usize),
 
     Input(RefCell<FlowStreamer>),
     Output(RefCell<FlowListener>),
 
+    /// Takes one value per pattern off the stack, the first pattern
+    /// matching the deepest value, and jumps if all of them match.
     Case(Vec<MatchPattern>, usize),
+
+    // superinstructions, only made by `peephole`
+    /// `Load`, `Load` and `BinExpr` in one.
+    LoadBin(Var, Var, ActionV),
+    /// `BinExpr` and `Store` in one.
+    BinStore(ActionV, Var),
+    /// A comparison and a jump if it is false, as `Case(false)` after it.
+    JumpUnless(ComparsionV, usize),
+}
+impl IR {
+    pub fn target(&self) -> Option<usize> {
+        match self {
+            IR::Jump(target)
+            | IR::Test(_, target)
+            | IR::Unwind(_, target)
+            | IR::Function(_, _, target)
+            | IR::Case(_, target)
+            | IR::JumpUnless(_, target) => Some(*target),
+            _ => None,
+        }
+    }
+    /// The jump target of the instruction, if it has one.
+    pub fn target_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            IR::Jump(target)
+            | IR::Test(_, target)
+            | IR::Unwind(_, target)
+            | IR::Function(_, _, target)
+            | IR::Case(_, target)
+            | IR::JumpUnless(_, target) => Some(target),
+            _ => None,
+        }
+    }
+}
+/// Builds a program whose jumps name labels rather than positions; `link`
+/// resolves them once every label is placed.
+struct Assembler {
+    code: Vec<IR>,
+    /// Position of each label, once placed.
+    labels: Vec<Option<usize>>,
+    /// The enclosing blocks, innermost last.
+    blocks: Vec<Block>,
+}
+struct Block {
+    /// Labels `Statement::Jump` restarts and leaves the block with.
+    start: usize,
+    end: usize,
+    /// Slots the block's frame needs, from the variables used in it.
+    slots: usize,
+}
+impl Assembler {
+    fn new() -> Self {
+        Assembler { code: Vec::new(), labels: Vec::new(), blocks: Vec::new() }
+    }
+    fn label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+    /// Makes `label` refer to the next instruction emitted.
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+    fn emit(&mut self, instruction: IR) {
+        match &instruction {
+            IR::Store(var) | IR::Load(var) => self.note(*var),
+            IR::Case(patterns, _) => {
+                for var in patterns.iter().flat_map(MatchPattern::bindings) {
+                    self.note(var);
+                }
+            }
+            _ => (),
+        }
+        self.code.push(instruction);
+    }
+    /// Makes room for `var` in the frame of the block it belongs to.
+    fn note(&mut self, var: Var) {
+        if let Some(index) = self.blocks.len().checked_sub(var.depth + 1) {
+            let block = &mut self.blocks[index];
+            block.slots = block.slots.max(var.slot + 1);
+        }
+    }
+    /// Emits `body` as the code of a block whose restart and exit jumps go
+    /// to `start` and `end`, returning the slots it uses.
+    fn scoped(&mut self, start: usize, end: usize, body: impl FnOnce(&mut Self)) -> usize {
+        self.blocks.push(Block { start, end, slots: 0 });
+        body(self);
+        self.blocks.pop().map_or(0, |block| block.slots)
+    }
+    /// `Enter`, `body` and `Leave`, with jumps restarting the block landing
+    /// right after `Enter`.
+    fn block(&mut self, body: impl FnOnce(&mut Self, usize, usize)) {
+        let (start, end) = (self.label(), self.label());
+        let enter = self.code.len();
+        self.emit(IR::Enter(0));
+        self.place(start);
+        let slots = self.scoped(start, end, |this| body(this, start, end));
+        self.code[enter] = IR::Enter(slots);
+        self.place(end);
+        self.emit(IR::Leave);
+    }
+    fn link(mut self) -> Vec<IR> {
+        for instruction in &mut self.code {
+            if let Some(target) = instruction.target_mut() {
+                *target = self.labels[*target].expect("jump to a label that was never placed");
+            }
+        }
+        self.code
+    }
+}
+/// Lowers `statements` into a flat program that runs in the caller's
+/// innermost frame, and verifies it: the parser accepts some programs that
+/// take values nothing leaves, such as `~- ()`.
+pub fn ast_to_ir(statements: &[Statement]) -> Result<Vec<IR>, VerifyError> {
+    let mut asm = Assembler::new();
+    let (start, end) = (asm.label(), asm.label());
+    asm.place(start);
+    asm.scoped(start, end, |asm| {
+        for statement in statements {
+            lower(statement, asm);
+        }
+    });
+    asm.place(end);
+    let code = asm.link();
+    verify(&code)?;
+    Ok(code)
 }
-pub fn ast_to_ir(ast_node: &Statement, ir: &mut Vec<IR>) {
+fn lower(ast_node: &Statement, asm: &mut Assembler) {
     match ast_node {
-        Statement::Block(vec) =>{
-            let mut ir_block: Vec<IR> = Vec::new();
+        Statement::Block(vec) => asm.block(|asm, _, _| {
             for node in vec {
-                ast_to_ir(&node, &mut ir_block);
+                lower(node, asm);
             }
-            ir.push(IR::Efine(ir_block));
-        },
+        }),
         
-        Statement::Set { name, value } => {
-            ast_to_ir(value, ir);
-            ir.push(IR::Store(*name));
+        Statement::Set { var, value } => {
+            lower(value, asm);
+            asm.emit(IR::Store(*var));
         }
-        Statement::Nil => ir.push(IR::Nil),
-        Statement::Name(s) => {
-            ir.push(IR::Load(s.clone()));
+        Statement::Nil => asm.emit(IR::Nil),
+        Statement::Name(var) => {
+            asm.emit(IR::Load(*var));
         }
-        Statement::Bool(v) => ir.push(IR::Bool(*v)),
-        Statement::Number(v) => ir.push(IR::Num(*v)),
+        Statement::Bool(v) => asm.emit(IR::Bool(*v)),
+        Statement::Number(v) => asm.emit(IR::Num(*v)),
+        Statement::Float(v) => asm.emit(IR::Float(*v)),
+        #[cfg(feature = "bigint")]
+        Statement::BigNumber(v) => asm.emit(IR::BigNum(v.clone())),
+        Statement::Str(v) => asm.emit(IR::Str(v.clone())),
         Statement::Comparsion(comparsion_type, statement, statement1) => {
-            ast_to_ir(statement, ir);
-            ast_to_ir(statement1, ir);
-            ir.push(match comparsion_type {
+            lower(statement, asm);
+            lower(statement1, asm);
+            asm.emit(match comparsion_type {
                 ComparsionV::Equal => IR::Eql,
                 ComparsionV::NotEqual => IR::NEql,
                 ComparsionV::Less => IR::Ls,
@@ -71,12 +268,25 @@ pub fn ast_to_ir(ast_node: &Statement, ir: &mut Vec<IR>) {
                 ComparsionV::GreaterOrEqual => IR::GtEql,
             });
         }
+        // `a & b` is `a, Test(false, end), b, Bool(true), And`: a false left
+        // side is the result and the right side is never evaluated, a true
+        // one is dropped and the right side type-checked against `true`
+        Statement::OperationBool(action @ (ActionV::And | ActionV::Or), statement, Some(statement1)) => {
+            let is_or = *action == ActionV::Or;
+            let end = asm.label();
+            lower(statement, asm);
+            asm.emit(IR::Test(is_or, end));
+            lower(statement1, asm);
+            asm.emit(IR::Bool(!is_or));
+            asm.emit(if is_or { IR::Or } else { IR::And });
+            asm.place(end);
+        }
         Statement::OperationBool(action_type, statement, statement1) => {
-            ast_to_ir(&statement, ir);
+            lower(statement, asm);
             if let Some(statement1) = statement1 {
-                ast_to_ir(&statement1, ir);
+                lower(statement1, asm);
             }
-            ir.push(match action_type {
+            asm.emit(match action_type {
                 ActionV::Not => IR::Not,
                 ActionV::And => IR::And,
                 ActionV::Or => IR::Or,
@@ -85,27 +295,210 @@ pub fn ast_to_ir(ast_node: &Statement, ir: &mut Vec<IR>) {
         }
         
         Statement::OperationNumder(action_type, statement, statement1) => {
-            ast_to_ir(&statement, ir);
-            ast_to_ir(&statement1, ir);
-            ir.push(IR::BinExpr(action_type.clone()));
+            lower(statement, asm);
+            lower(statement1, asm);
+            asm.emit(IR::BinExpr(action_type.clone()));
         }
         Statement::If(statement, statement1, statement2) => {
-            ast_to_ir(&statement, ir);
-            ir.push(IR::Case(
-                vec![MatchPattern::Val(vec![IR::Bool(false)])],
-                ir.len() + 2 + if statement2.is_some() { 1 } else { 0 }, // jump over the "then" block and optional "else" block
-            ));
-            ast_to_ir(&statement1, ir);
+            let (otherwise, end) = (asm.label(), asm.label());
+            lower(statement, asm);
+            asm.emit(is_false(otherwise));
+            lower(statement1, asm);
             if let Some(statement2) = statement2 {
-                ir.push(IR::Jump(ir.len() + 2)); // jump over the "else" block
-                ast_to_ir(&statement2, ir);
+                asm.emit(IR::Jump(end));
+                asm.place(otherwise);
+                lower(statement2, asm);
+            } else {
+                asm.place(otherwise);
             }
+            asm.place(end);
         }
         Statement::Out { expr, to } => {
-            ast_to_ir(&expr, ir);
-            ir.push(IR::Output(to.clone()));
+            lower(expr, asm);
+            asm.emit(IR::Output(to.clone()));
+        }
+        Statement::In(streamer) => asm.emit(IR::Input(streamer.clone())),
+        Statement::Jump { repeat, depth } => {
+            let block = &asm.blocks[asm.blocks.len() - 1 - depth];
+            let target = if *repeat { block.start } else { block.end };
+            asm.emit(if *depth == 0 { IR::Jump(target) } else { IR::Unwind(*depth, target) });
+        }
+        // the body is laid out in place and skipped; `End` returns what is
+        // left on its stack
+        Statement::Function { name, params, body } => {
+            let (entry, end, after) = (asm.label(), asm.label(), asm.label());
+            asm.emit(IR::Function(*name, params.len(), entry));
+            asm.emit(IR::Jump(after));
+            asm.place(entry);
+            let outer = std::mem::take(&mut asm.blocks);
+            asm.scoped(entry, end, |asm| lower(body, asm));
+            asm.blocks = outer;
+            asm.place(end);
+            asm.emit(IR::End);
+            asm.place(after);
+        }
+        Statement::Call { name, args } => {
+            for arg in args {
+                lower(arg, asm);
+            }
+            asm.emit(IR::Call(*name, args.len()));
+        }
+        Statement::Return(value) => {
+            if let Some(value) = value {
+                lower(value, asm);
+            }
+            asm.emit(IR::Ret(value.is_some()));
+        }
+        Statement::List(items) => {
+            for item in items {
+                lower(item, asm);
+            }
+            asm.emit(IR::List(items.len()));
+        }
+        Statement::Index { list, index } => {
+            lower(list, asm);
+            lower(index, asm);
+            asm.emit(IR::Index);
+        }
+        Statement::Slice { list, start, end } => {
+            lower(list, asm);
+            for bound in [start, end].into_iter().flatten() {
+                lower(bound, asm);
+            }
+            asm.emit(IR::Slice(start.is_some(), end.is_some()));
+        }
+        Statement::Length(value) => {
+            lower(value, asm);
+            asm.emit(IR::Len);
+        }
+        Statement::SetIndex { var, indices, value } => {
+            asm.emit(IR::Load(*var));
+            for index in indices {
+                lower(index, asm);
+            }
+            lower(value, asm);
+            asm.emit(IR::SetItem(indices.len()));
+            asm.emit(IR::Store(*var));
         }
-        Statement::In(streamer) => ir.push(IR::Input(streamer.clone())),
-        Statement::Jump(t) => ir.push(IR::Jump(if *t { 0 } else { usize::MAX })),
+        // the tests of all arms come first and jump to their bodies, a guard
+        // that fails resumes with the next arm's test
+        Statement::Match { subject, temps, arms } => asm.block(|asm, _, end| {
+            for value in subject {
+                lower(value, asm);
+            }
+            for temp in temps.iter().rev() {
+                asm.emit(IR::Store(*temp));
+            }
+            let tests: Vec<usize> = arms.iter().map(|_| asm.label()).collect();
+            let bodies: Vec<usize> = arms.iter().map(|_| asm.label()).collect();
+            let no_match = asm.label();
+            for (arm, (test, body)) in arms.iter().zip(tests.iter().zip(&bodies)) {
+                asm.place(*test);
+                for temp in temps {
+                    asm.emit(IR::Load(*temp));
+                }
+                asm.emit(IR::Case(arm.patterns.iter().map(pattern_to_ir).collect(), *body));
+            }
+            asm.place(no_match);
+            asm.emit(IR::Jump(end));
+            for (i, arm) in arms.iter().enumerate() {
+                asm.place(bodies[i]);
+                if let Some(guard) = &arm.guard {
+                    lower(guard, asm);
+                    asm.emit(is_false(tests.get(i + 1).copied().unwrap_or(no_match)));
+                }
+                lower(&arm.body, asm);
+                asm.emit(IR::Jump(end));
+            }
+        }),
+        Statement::Range(start, end) => {
+            lower(start, asm);
+            lower(end, asm);
+            asm.emit(IR::Range);
+        }
+        // the loop is a block that checks its condition first and restarts
+        // itself at the end
+        Statement::While { condition, body } => asm.block(|asm, start, end| {
+            lower(condition, asm);
+            asm.emit(is_false(end));
+            for node in body {
+                lower(node, asm);
+            }
+            asm.emit(IR::Jump(start));
+        }),
+        // an outer block sets up `source` and `counter`, the inner one is
+        // the loop, stepping the counter before the body so `-> |+` works
+        Statement::For { var, iterable, source, counter, body } => asm.block(|asm, _, _| {
+            let (source_up, counter_up) = (outer(*source), outer(*counter));
+            let range = match iterable.as_ref() {
+                Statement::Spanned(_, statement) => statement.as_ref(),
+                statement => statement,
+            };
+            if let Statement::Range(start, end) = range {
+                lower(start, asm);
+                asm.emit(IR::Store(*counter));
+                lower(end, asm);
+                asm.emit(IR::Store(*source));
+            } else {
+                lower(iterable, asm);
+                asm.emit(IR::Store(*source));
+                asm.emit(IR::Num(0));
+                asm.emit(IR::Store(*counter));
+            }
+            asm.block(|asm, start, end| {
+                asm.emit(IR::Load(counter_up));
+                asm.emit(IR::Load(source_up));
+                if let Statement::Range(..) = range {
+                    asm.emit(IR::Ls);
+                    asm.emit(is_false(end));
+                    asm.emit(IR::Load(counter_up));
+                } else {
+                    asm.emit(IR::Len);
+                    asm.emit(IR::Ls);
+                    asm.emit(is_false(end));
+                    asm.emit(IR::Load(source_up));
+                    asm.emit(IR::Load(counter_up));
+                    asm.emit(IR::Index);
+                }
+                asm.emit(IR::Store(*var));
+                for instruction in [IR::Load(counter_up), IR::Num(1), IR::BinExpr(ActionV::Add), IR::Store(counter_up)] {
+                    asm.emit(instruction);
+                }
+                for node in body {
+                    lower(node, asm);
+                }
+                asm.emit(IR::Jump(start));
+            });
+        }),
+        Statement::Spanned(_, statement) => lower(statement, asm),
     }
-}
\ No newline at end of file
+}
+/// Jumps to `target` if the value on top of the stack is false.
+fn is_false(target: usize) -> IR {
+    IR::Case(vec![MatchPattern::Val(VarV::Bool(false))], target)
+}
+fn pattern_to_ir(pattern: &Pattern) -> MatchPattern {
+    match pattern {
+        Pattern::Wildcard => MatchPattern::Unused,
+        Pattern::Bind(var) => MatchPattern::Var(*var),
+        Pattern::Value(value) => MatchPattern::Val(constant(value)),
+        Pattern::Tuple(patterns) => MatchPattern::Tuple(patterns.iter().map(pattern_to_ir).collect()),
+    }
+}
+/// The value of a literal pattern.
+fn constant(value: &Statement) -> VarV {
+    match value {
+        Statement::Number(v) => VarV::Num(*v),
+        Statement::Float(v) => VarV::Float(*v),
+        #[cfg(feature = "bigint")]
+        Statement::BigNumber(v) => VarV::Big(v.clone()),
+        Statement::Str(v) => VarV::Str(v.clone()),
+        Statement::Bool(v) => VarV::Bool(*v),
+        Statement::Spanned(_, statement) => constant(statement),
+        _ => unreachable!("the parser only accepts literals as patterns"),
+    }
+}
+/// `var` as seen from a block nested one level deeper.
+fn outer(var: Var) -> Var {
+    Var { depth: var.depth + 1, ..var }
+}
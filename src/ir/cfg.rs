@@ -0,0 +1,122 @@
+//! The basic blocks of a lowered program and how control passes between
+//! them, and that graph in Graphviz DOT form for `--cfg`.
+use std::collections::BTreeSet;
+
+use super::text::Line;
+use super::{Program, IR};
+use crate::inout::dot_string;
+
+/// How control gets from one block to another.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    /// Running off the end of a block into the next one.
+    Fallthrough,
+    /// `Jump` or `Unwind`.
+    Jump,
+    /// The taken side of `Case`, `Test` or `JumpUnless`.
+    Case,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    /// Index of the block control passes to.
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+/// A run of instructions only entered at its first one and only left
+/// after its last.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    /// Index of the first instruction, and one past the last.
+    pub start: usize,
+    pub end: usize,
+    pub edges: Vec<Edge>,
+}
+/// The blocks in program order. The last one is empty and stands for the
+/// end of the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    /// For each `IR::Function`, its index and the block its body starts at.
+    pub entries: Vec<(usize, usize)>,
+}
+/// Splits `code` where jumps land and after every instruction that may
+/// not fall through. Registering a function is not an edge: its body is
+/// only entered by calls.
+pub fn control_flow(code: &[IR]) -> Cfg {
+    let mut starts: BTreeSet<usize> = BTreeSet::from([0, code.len()]);
+    for (index, instruction) in code.iter().enumerate() {
+        starts.extend(instruction.target().filter(|target| *target <= code.len()));
+        if ends_block(instruction) {
+            starts.insert(index + 1);
+        }
+    }
+    let starts: Vec<usize> = starts.into_iter().collect();
+    let block_at = |index: usize| starts.partition_point(|start| *start < index);
+    let mut blocks: Vec<BasicBlock> = starts
+        .windows(2)
+        .map(|bounds| {
+            let (start, end) = (bounds[0], bounds[1]);
+            let edge = |target: usize, kind: EdgeKind| Edge { to: block_at(target), kind };
+            let edges = match &code[end - 1] {
+                IR::Jump(target) | IR::Unwind(_, target) => vec![edge(*target, EdgeKind::Jump)],
+                IR::Test(_, target) | IR::Case(_, target) | IR::JumpUnless(_, target) => {
+                    vec![edge(*target, EdgeKind::Case), edge(end, EdgeKind::Fallthrough)]
+                }
+                IR::Ret(_) | IR::End => Vec::new(),
+                _ => vec![edge(end, EdgeKind::Fallthrough)],
+            };
+            BasicBlock { start, end, edges }
+        })
+        .collect();
+    blocks.push(BasicBlock { start: code.len(), end: code.len(), edges: Vec::new() });
+    let entries = code
+        .iter()
+        .enumerate()
+        .filter_map(|(index, instruction)| match instruction {
+            IR::Function(_, _, entry) if *entry <= code.len() => Some((index, block_at(*entry))),
+            _ => None,
+        })
+        .collect();
+    Cfg { blocks, entries }
+}
+fn ends_block(instruction: &IR) -> bool {
+    matches!(
+        instruction,
+        IR::Jump(_) | IR::Unwind(..) | IR::Test(..) | IR::Case(..) | IR::JumpUnless(..) | IR::Ret(_) | IR::End
+    )
+}
+/// The control flow graph of `program` as a DOT digraph: a box per block
+/// listing its instructions, and a dotted edge from each function's
+/// registration to its body.
+pub fn cfg_dot(program: &Program) -> String {
+    let cfg = control_flow(&program.code);
+    let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+    for (index, block) in cfg.blocks.iter().enumerate() {
+        if index == cfg.blocks.len() - 1 {
+            out.push_str(&format!("    b{} [label=\"end\", shape=oval];\n", index));
+            continue;
+        }
+        let mut label = format!("L{}:\n", block.start);
+        for instruction in &program.code[block.start..block.end] {
+            label.push_str(&format!("    {}\n", Line(instruction, &program.names)));
+        }
+        out.push_str(&format!("    b{} [label={}];\n", index, dot_string(&label)));
+    }
+    for (index, block) in cfg.blocks.iter().enumerate() {
+        for edge in &block.edges {
+            let style = match edge.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Jump => " [style=bold]",
+                EdgeKind::Case => " [style=dashed, label=\"case\"]",
+            };
+            out.push_str(&format!("    b{} -> b{}{};\n", index, edge.to, style));
+        }
+    }
+    for (at, entry) in &cfg.entries {
+        let from = cfg.blocks.partition_point(|block| block.end <= *at);
+        let label = dot_string(&Line(&program.code[*at], &program.names).to_string());
+        out.push_str(&format!("    b{} -> b{} [style=dotted, label={}];\n", from, entry, label));
+    }
+    out.push_str("}\n");
+    out
+}
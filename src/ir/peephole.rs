@@ -0,0 +1,205 @@
+//! Rewrites a linked program into fewer instructions, run after lowering
+//! with `-O`. Jumps to jumps go straight to where the chain ends, `Nil` and
+//! jumps to the next instruction are dropped, and common sequences become
+//! the superinstructions `LoadBin`, `BinStore` and `JumpUnless`. A
+//! sequence is only fused when nothing jumps into its middle.
+use std::collections::HashSet;
+
+use super::{verify, MatchPattern, VerifyError, IR};
+use crate::types::ComparsionV;
+use crate::vm::VarV;
+
+/// Rewrites `code` until none of the rules applies any more, and verifies
+/// the result.
+pub fn peephole(mut code: Vec<IR>) -> Result<Vec<IR>, VerifyError> {
+    loop {
+        thread(&mut code);
+        let len = code.len();
+        code = rewrite(code);
+        if code.len() == len {
+            break;
+        }
+    }
+    verify(&code)?;
+    Ok(code)
+}
+/// Points every jump at the end of the chain of `Jump`s it lands on.
+fn thread(code: &mut [IR]) {
+    for index in 0..code.len() {
+        let Some(mut target) = code[index].target() else {
+            continue;
+        };
+        // a chain can loop, so give up after visiting every instruction
+        for _ in 0..code.len() {
+            match code.get(target) {
+                Some(IR::Jump(next)) if *next != target => target = *next,
+                _ => break,
+            }
+        }
+        if let Some(old) = code[index].target_mut() {
+            *old = target;
+        }
+    }
+}
+/// One pass of dropping and fusing, with the targets moved to match.
+fn rewrite(code: Vec<IR>) -> Vec<IR> {
+    let targets: HashSet<usize> = code.iter().filter_map(IR::target).collect();
+    // where each old index went; dropped instructions go to whatever
+    // follows them
+    let mut moved: Vec<usize> = vec![0; code.len() + 1];
+    let mut out: Vec<IR> = Vec::new();
+    let mut index = 0;
+    while index < code.len() {
+        moved[index] = out.len();
+        let (instruction, taken) = match fuse(&code[index..], |at| targets.contains(&(index + at))) {
+            Some(fused) => fused,
+            None => match &code[index] {
+                IR::Nil => {
+                    index += 1;
+                    continue;
+                }
+                IR::Jump(target) if *target == index + 1 => {
+                    index += 1;
+                    continue;
+                }
+                instruction => (instruction.clone(), 1),
+            },
+        };
+        for at in 1..taken {
+            moved[index + at] = out.len();
+        }
+        out.push(instruction);
+        index += taken;
+    }
+    moved[code.len()] = out.len();
+    for instruction in &mut out {
+        if let Some(target) = instruction.target_mut() {
+            *target = moved[*target];
+        }
+    }
+    out
+}
+/// The superinstruction the start of `code` can become and how many
+/// instructions it replaces. `is_target` tells whether something jumps to
+/// the instruction at an offset.
+fn fuse(code: &[IR], is_target: impl Fn(usize) -> bool) -> Option<(IR, usize)> {
+    let fused = match code {
+        [IR::Load(left), IR::Load(right), IR::BinExpr(action), ..] if !is_target(1) && !is_target(2) => {
+            (IR::LoadBin(*left, *right, action.clone()), 3)
+        }
+        [IR::BinExpr(action), IR::Store(var), ..] if !is_target(1) => (IR::BinStore(action.clone(), *var), 2),
+        [comparison, IR::Case(patterns, target), ..] if !is_target(1) && is_false(patterns) => {
+            (IR::JumpUnless(comparsion(comparison)?, *target), 2)
+        }
+        _ => return None,
+    };
+    Some(fused)
+}
+/// Whether a `Case` only tests for `false`, as conditions lower to.
+fn is_false(patterns: &[MatchPattern]) -> bool {
+    matches!(patterns, [MatchPattern::Val(VarV::Bool(false))])
+}
+fn comparsion(instruction: &IR) -> Option<ComparsionV> {
+    let comparsion = match instruction {
+        IR::Eql => ComparsionV::Equal,
+        IR::NEql => ComparsionV::NotEqual,
+        IR::Ls => ComparsionV::Less,
+        IR::Gt => ComparsionV::Greater,
+        IR::LsEql => ComparsionV::LessOrEqual,
+        IR::GtEql => ComparsionV::GreaterOrEqual,
+        _ => return None,
+    };
+    Some(comparsion)
+}
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::ir::assemble;
+    use crate::types::FlowListener;
+    use crate::vm::{execute, Options, Runtime, Scope};
+
+    /// Sums 0 to 4 in a loop, as lowering lays one out.
+    const LOOP: &str = "
+.names i sum
+    enter 2
+        num 0
+        store i@0.0
+        num 0
+        store sum@0.1
+    L5:
+        load i@0.0
+        num 5
+        lt
+        case false L18
+        load sum@0.1
+        load i@0.0
+        add
+        store sum@0.1
+        load i@0.0
+        num 1
+        add
+        store i@0.0
+        jump L5
+    L18:
+        load sum@0.1
+        output
+        leave
+";
+    /// Adds `a` and `b` only when `a` is 1, jumping between the two loads
+    /// otherwise.
+    const JUMP_INTO: &str = "
+.names a b
+    enter 2
+        num 1
+        store a@0.0
+        num 2
+        store b@0.1
+        load a@0.0
+        case 1 L11
+        num 100
+        jump L12
+    L11:
+        load a@0.0
+    L12:
+        load b@0.1
+        add
+        output
+        leave
+";
+    fn code(listing: &str) -> Vec<IR> {
+        assemble(listing).expect("test listing does not assemble").code
+    }
+    /// What `code` prints when run.
+    fn output(mut code: Vec<IR>) -> Vec<String> {
+        let lines = Rc::new(RefCell::new(Vec::new()));
+        for instruction in &mut code {
+            if let IR::Output(listener) = instruction {
+                *listener = RefCell::new(FlowListener::Capture(lines.clone()));
+            }
+        }
+        execute(&code, &mut vec![Scope::new()], &mut Runtime::new(Options::default())).expect("test code fails");
+        lines.take()
+    }
+    #[test]
+    fn fuses_a_loop() {
+        let original = code(LOOP);
+        let optimized = peephole(original.clone()).expect("peephole gives invalid code");
+        assert!(optimized.iter().any(|instruction| matches!(instruction, IR::LoadBin(..))));
+        assert!(optimized.iter().any(|instruction| matches!(instruction, IR::BinStore(..))));
+        assert!(optimized.iter().any(|instruction| matches!(instruction, IR::JumpUnless(ComparsionV::Less, _))));
+        assert_eq!((original.len(), optimized.len()), (21, 17));
+        assert_eq!(verify(&optimized), Ok(()));
+        assert_eq!(output(optimized), output(original));
+    }
+    #[test]
+    fn keeps_a_sequence_jumped_into() {
+        let original = code(JUMP_INTO);
+        let optimized = peephole(original.clone()).expect("peephole gives invalid code");
+        assert!(!optimized.iter().any(|instruction| matches!(instruction, IR::LoadBin(..))));
+        assert_eq!(verify(&optimized), Ok(()));
+        assert_eq!(output(optimized), ["3"]);
+    }
+}
@@ -0,0 +1,556 @@
+//! A textual form of `Program`: one instruction per line, jump targets as
+//! labels, and block and function bodies indented.
+//!
+//! ```text
+//! .names fact n
+//!     enter 1
+//! L2:
+//!     load n@0.0
+//!     case false L9
+//! ```
+//!
+//! A variable is written `name@depth.slot`, with `#id` for an id without a
+//! name and `$` for a compiler temporary. `.names` lists the names by id so
+//! a listing assembles back to the same ids; names missing from it get the
+//! next free id. A tuple pattern of `case` is its patterns between `(` and
+//! `)`. `;` starts a comment.
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use super::{MatchPattern, Program, IR};
+use crate::types::{ActionV, ComparsionV, FlowListener, FlowStreamer, Var};
+use crate::vm::VarV;
+
+/// A line of a listing that could not be assembled.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+pub fn disassemble(program: &Program) -> String {
+    let targets: BTreeSet<usize> = program.code.iter().filter_map(IR::target).collect();
+    let entries: BTreeSet<usize> = program.code.iter().filter_map(|instruction| match instruction {
+        IR::Function(_, _, entry) => Some(*entry),
+        _ => None,
+    }).collect();
+    let mut out = String::new();
+    if !program.names.is_empty() {
+        out.push_str(".names");
+        for name in &program.names {
+            out.push(' ');
+            out.push_str(name);
+        }
+        out.push('\n');
+    }
+    let mut depth: usize = 1;
+    for (index, instruction) in program.code.iter().enumerate() {
+        if entries.contains(&index) {
+            depth += 1;
+        }
+        if let IR::Leave = instruction {
+            depth = depth.saturating_sub(1);
+        }
+        if targets.contains(&index) {
+            out.push_str(&format!("{}L{}:\n", indent(depth.saturating_sub(1)), index));
+        }
+        out.push_str(&format!("{}{}\n", indent(depth), Line(instruction, &program.names)));
+        match instruction {
+            IR::Enter(_) => depth += 1,
+            IR::End => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+    if targets.contains(&program.code.len()) {
+        out.push_str(&format!("L{}:\n", program.code.len()));
+    }
+    out
+}
+fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
+/// An instruction as written in a listing.
+pub(super) struct Line<'a>(pub(super) &'a IR, pub(super) &'a [String]);
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = self.1;
+        match self.0 {
+            IR::Num(n) => write!(f, "num {}", n),
+            IR::Float(n) => write!(f, "float {:?}", n),
+            #[cfg(feature = "bigint")]
+            IR::BigNum(n) => write!(f, "big {}", n),
+            IR::Bool(b) => write!(f, "bool {}", b),
+            IR::Str(s) => write!(f, "str {:?}", s),
+            IR::Nil => write!(f, "nil"),
+            IR::BinExpr(action @ (ActionV::Not | ActionV::And | ActionV::Or)) => {
+                write!(f, "binexpr {}", action_name(action))
+            }
+            IR::BinExpr(action) => write!(f, "{}", action_name(action)),
+            IR::Not => write!(f, "not"),
+            IR::Or => write!(f, "or"),
+            IR::And => write!(f, "and"),
+            IR::Eql => write!(f, "eq"),
+            IR::NEql => write!(f, "ne"),
+            IR::Ls => write!(f, "lt"),
+            IR::Gt => write!(f, "gt"),
+            IR::LsEql => write!(f, "le"),
+            IR::GtEql => write!(f, "ge"),
+            IR::Store(var) => write!(f, "store {}", VarText(*var, names)),
+            IR::Load(var) => write!(f, "load {}", VarText(*var, names)),
+            IR::Jump(target) => write!(f, "jump L{}", target),
+            IR::Test(flag, target) => write!(f, "test {} L{}", flag, target),
+            IR::Unwind(depth, target) => write!(f, "unwind {} L{}", depth, target),
+            IR::Enter(slots) => write!(f, "enter {}", slots),
+            IR::Leave => write!(f, "leave"),
+            IR::Function(name, arity, entry) => {
+                write!(f, "function {} {} L{}", NameText(*name, names), arity, entry)
+            }
+            IR::Call(name, argc) => write!(f, "call {} {}", NameText(*name, names), argc),
+            IR::Ret(true) => write!(f, "ret value"),
+            IR::Ret(false) => write!(f, "ret"),
+            IR::End => write!(f, "end"),
+            IR::List(len) => write!(f, "list {}", len),
+            IR::Index => write!(f, "index"),
+            IR::Slice(has_start, has_end) => write!(f, "slice {} {}", has_start, has_end),
+            IR::Len => write!(f, "len"),
+            IR::Range => write!(f, "range"),
+            IR::SetItem(depth) => write!(f, "setitem {}", depth),
+            IR::Input(streamer) => match *streamer.borrow() {
+                FlowStreamer::Console => write!(f, "input console"),
+                FlowStreamer::None => write!(f, "input none"),
+            },
+            IR::Output(_) => write!(f, "output"),
+            IR::Case(patterns, target) => {
+                write!(f, "case")?;
+                for pattern in patterns {
+                    write!(f, " {}", PatternText(pattern, names))?;
+                }
+                write!(f, " L{}", target)
+            }
+            IR::LoadBin(left, right, action) => {
+                write!(f, "loadbin {} {} {}", action_name(action), VarText(*left, names), VarText(*right, names))
+            }
+            IR::BinStore(action, var) => write!(f, "binstore {} {}", action_name(action), VarText(*var, names)),
+            IR::JumpUnless(comparsion, target) => {
+                write!(f, "jumpunless {} L{}", comparsion_name(comparsion), target)
+            }
+        }
+    }
+}
+fn action_name(action: &ActionV) -> &'static str {
+    match action {
+        ActionV::Add => "add",
+        ActionV::Sub => "sub",
+        ActionV::Mul => "mul",
+        ActionV::Div => "div",
+        ActionV::Mod => "mod",
+        ActionV::Not => "not",
+        ActionV::And => "and",
+        ActionV::Or => "or",
+    }
+}
+/// The mnemonic of the instruction making the same comparison.
+fn comparsion_name(comparsion: &ComparsionV) -> &'static str {
+    match comparsion {
+        ComparsionV::Equal => "eq",
+        ComparsionV::NotEqual => "ne",
+        ComparsionV::Less => "lt",
+        ComparsionV::Greater => "gt",
+        ComparsionV::LessOrEqual => "le",
+        ComparsionV::GreaterOrEqual => "ge",
+    }
+}
+/// A variable or function id by its name in `names`, or as `#id` if it
+/// has none there.
+pub(crate) struct NameText<'a>(pub(crate) usize, pub(crate) &'a [String]);
+impl fmt::Display for NameText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.1.get(self.0) {
+            _ if self.0 == usize::MAX => write!(f, "$"),
+            Some(name) if is_name(name) => write!(f, "{}", name),
+            _ => write!(f, "#{}", self.0),
+        }
+    }
+}
+struct VarText<'a>(Var, &'a [String]);
+impl fmt::Display for VarText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Var { name, depth, slot } = self.0;
+        write!(f, "{}@{}.{}", NameText(name, self.1), depth, slot)
+    }
+}
+struct PatternText<'a>(&'a MatchPattern, &'a [String]);
+impl fmt::Display for PatternText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            MatchPattern::Var(var) => write!(f, "{}", VarText(*var, self.1)),
+            MatchPattern::Val(value) => write!(f, "{}", Literal(value)),
+            MatchPattern::Unused => write!(f, "_"),
+            MatchPattern::Tuple(patterns) => {
+                write!(f, "(")?;
+                for pattern in patterns {
+                    write!(f, " {}", PatternText(pattern, self.1))?;
+                }
+                write!(f, " )")
+            }
+        }
+    }
+}
+/// A pattern constant, written so that its type can be told from it.
+struct Literal<'a>(&'a VarV);
+impl fmt::Display for Literal<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            VarV::Float(n) => write!(f, "{:?}", n),
+            VarV::Str(s) => write!(f, "{:?}", s),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+pub fn assemble(text: &str) -> Result<Program, AsmError> {
+    let mut names = Names::default();
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut lines: Vec<(usize, Vec<String>)> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line_number = number + 1;
+        let error = |message: String| AsmError { line: line_number, message };
+        let words = split(line).map_err(error)?;
+        match words.first().map(String::as_str) {
+            None => (),
+            Some(".names") => {
+                for name in &words[1..] {
+                    names.ids.insert(name.clone(), names.table.len());
+                    names.table.push(name.clone());
+                }
+            }
+            Some(label) if label.ends_with(':') && words.len() == 1 => {
+                let label = label.trim_end_matches(':').to_string();
+                if labels.insert(label.clone(), lines.len()).is_some() {
+                    return Err(error(format!("label {} is defined twice", label)));
+                }
+            }
+            Some(_) => lines.push((line_number, words)),
+        }
+    }
+    let mut code: Vec<IR> = Vec::new();
+    for (line, words) in &lines {
+        let mut operands = Operands { words: &words[1..], labels: &labels, names: &mut names };
+        let instruction = read_line(&words[0], &mut operands)
+            .and_then(|instruction| operands.finish().map(|_| instruction))
+            .map_err(|message| AsmError { line: *line, message })?;
+        code.push(instruction);
+    }
+    Ok(Program { code, names: names.table })
+}
+#[derive(Default)]
+struct Names {
+    table: Vec<String>,
+    ids: HashMap<String, usize>,
+}
+impl Names {
+    fn id(&mut self, word: &str) -> Result<usize, String> {
+        if word == "$" {
+            return Ok(usize::MAX);
+        }
+        if let Some(id) = word.strip_prefix('#') {
+            return id.parse().map_err(|_| format!("invalid name id {:?}", word));
+        }
+        if !is_name(word) {
+            return Err(format!("invalid name {:?}", word));
+        }
+        if let Some(id) = self.ids.get(word) {
+            return Ok(*id);
+        }
+        self.ids.insert(word.to_string(), self.table.len());
+        self.table.push(word.to_string());
+        Ok(self.table.len() - 1)
+    }
+}
+/// The operands of one instruction, taken from the left.
+struct Operands<'a> {
+    words: &'a [String],
+    labels: &'a HashMap<String, usize>,
+    names: &'a mut Names,
+}
+impl Operands<'_> {
+    fn next(&mut self) -> Result<&str, String> {
+        let (word, rest) = self.words.split_first().ok_or("missing operand")?;
+        self.words = rest;
+        Ok(word)
+    }
+    fn finish(&self) -> Result<(), String> {
+        match self.words.first() {
+            Some(word) => Err(format!("unexpected operand {:?}", word)),
+            None => Ok(()),
+        }
+    }
+    fn uint(&mut self) -> Result<usize, String> {
+        let word = self.next()?;
+        word.parse().map_err(|_| format!("expected a count, found {:?}", word))
+    }
+    fn flag(&mut self) -> Result<bool, String> {
+        match self.next()? {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            word => Err(format!("expected true or false, found {:?}", word)),
+        }
+    }
+    fn label(&mut self) -> Result<usize, String> {
+        let word = self.next()?.to_string();
+        self.labels.get(&word).copied().ok_or(format!("undefined label {:?}", word))
+    }
+    fn name(&mut self) -> Result<usize, String> {
+        let word = self.next()?.to_string();
+        self.names.id(&word)
+    }
+    fn var(&mut self) -> Result<Var, String> {
+        let word = self.next()?.to_string();
+        parse_var(&word, self.names)
+    }
+    fn literal(&mut self) -> Result<VarV, String> {
+        parse_literal(self.next()?)
+    }
+    fn action(&mut self) -> Result<ActionV, String> {
+        let word = self.next()?;
+        let action = match word {
+            "add" => ActionV::Add,
+            "sub" => ActionV::Sub,
+            "mul" => ActionV::Mul,
+            "div" => ActionV::Div,
+            "mod" => ActionV::Mod,
+            "not" => ActionV::Not,
+            "and" => ActionV::And,
+            "or" => ActionV::Or,
+            _ => return Err(format!("unknown operation {:?}", word)),
+        };
+        Ok(action)
+    }
+    fn comparsion(&mut self) -> Result<ComparsionV, String> {
+        let word = self.next()?;
+        let comparsion = match word {
+            "eq" => ComparsionV::Equal,
+            "ne" => ComparsionV::NotEqual,
+            "lt" => ComparsionV::Less,
+            "gt" => ComparsionV::Greater,
+            "le" => ComparsionV::LessOrEqual,
+            "ge" => ComparsionV::GreaterOrEqual,
+            _ => return Err(format!("unknown comparison {:?}", word)),
+        };
+        Ok(comparsion)
+    }
+}
+fn read_line(mnemonic: &str, operands: &mut Operands) -> Result<IR, String> {
+    let instruction = match mnemonic {
+        "num" => match parse_literal(operands.next()?)? {
+            VarV::Num(n) => IR::Num(n),
+            #[cfg(feature = "bigint")]
+            VarV::Big(n) => IR::BigNum(n),
+            value => return Err(format!("expected an integer, found {}", value.type_name())),
+        },
+        "float" => {
+            let word = operands.next()?;
+            IR::Float(word.parse().map_err(|_| format!("expected a float, found {:?}", word))?)
+        }
+        #[cfg(feature = "bigint")]
+        "big" => {
+            let word = operands.next()?;
+            IR::BigNum(word.parse().map_err(|_| format!("expected an integer, found {:?}", word))?)
+        }
+        "bool" => IR::Bool(operands.flag()?),
+        "str" => match operands.literal()? {
+            VarV::Str(s) => IR::Str(s),
+            value => return Err(format!("expected a string, found {}", value.type_name())),
+        },
+        "nil" => IR::Nil,
+        "add" => IR::BinExpr(ActionV::Add),
+        "sub" => IR::BinExpr(ActionV::Sub),
+        "mul" => IR::BinExpr(ActionV::Mul),
+        "div" => IR::BinExpr(ActionV::Div),
+        "mod" => IR::BinExpr(ActionV::Mod),
+        "binexpr" => IR::BinExpr(operands.action()?),
+        "not" => IR::Not,
+        "or" => IR::Or,
+        "and" => IR::And,
+        "eq" => IR::Eql,
+        "ne" => IR::NEql,
+        "lt" => IR::Ls,
+        "gt" => IR::Gt,
+        "le" => IR::LsEql,
+        "ge" => IR::GtEql,
+        "store" => IR::Store(operands.var()?),
+        "load" => IR::Load(operands.var()?),
+        "jump" => IR::Jump(operands.label()?),
+        "test" => IR::Test(operands.flag()?, operands.label()?),
+        "unwind" => IR::Unwind(operands.uint()?, operands.label()?),
+        "enter" => IR::Enter(operands.uint()?),
+        "leave" => IR::Leave,
+        "function" => IR::Function(operands.name()?, operands.uint()?, operands.label()?),
+        "call" => IR::Call(operands.name()?, operands.uint()?),
+        "ret" => match operands.words.first().map(String::as_str) {
+            Some("value") => {
+                operands.next()?;
+                IR::Ret(true)
+            }
+            _ => IR::Ret(false),
+        },
+        "end" => IR::End,
+        "list" => IR::List(operands.uint()?),
+        "index" => IR::Index,
+        "slice" => IR::Slice(operands.flag()?, operands.flag()?),
+        "len" => IR::Len,
+        "range" => IR::Range,
+        "setitem" => IR::SetItem(operands.uint()?),
+        "input" => IR::Input(RefCell::new(match operands.next()? {
+            "console" => FlowStreamer::Console,
+            "none" => FlowStreamer::None,
+            word => return Err(format!("expected console or none, found {:?}", word)),
+        })),
+        "output" => IR::Output(RefCell::new(FlowListener::Console)),
+        "case" => {
+            let (target, patterns) = operands.words.split_last().ok_or("missing operand")?;
+            let target = operands.labels.get(target).copied().ok_or(format!("undefined label {:?}", target))?;
+            let patterns = parse_patterns(&mut patterns.iter(), operands.names, false)?;
+            operands.words = &[];
+            IR::Case(patterns, target)
+        }
+        "loadbin" => {
+            let action = operands.action()?;
+            IR::LoadBin(operands.var()?, operands.var()?, action)
+        }
+        "binstore" => IR::BinStore(operands.action()?, operands.var()?),
+        "jumpunless" => IR::JumpUnless(operands.comparsion()?, operands.label()?),
+        _ => return Err(format!("unknown instruction {:?}", mnemonic)),
+    };
+    Ok(instruction)
+}
+fn parse_var(word: &str, names: &mut Names) -> Result<Var, String> {
+    let invalid = || format!("expected name@depth.slot, found {:?}", word);
+    let (name, place) = word.rsplit_once('@').ok_or_else(invalid)?;
+    let (depth, slot) = place.split_once('.').ok_or_else(invalid)?;
+    Ok(Var {
+        name: names.id(name)?,
+        depth: depth.parse().map_err(|_| invalid())?,
+        slot: slot.parse().map_err(|_| invalid())?,
+    })
+}
+/// `case` patterns up to the end of `words`, or up to the `)` that closes
+/// the tuple they are in if `is_nested`.
+fn parse_patterns(words: &mut std::slice::Iter<String>, names: &mut Names, is_nested: bool) -> Result<Vec<MatchPattern>, String> {
+    let mut patterns: Vec<MatchPattern> = Vec::new();
+    while let Some(word) = words.next() {
+        patterns.push(match word.as_str() {
+            ")" if is_nested => return Ok(patterns),
+            "(" => MatchPattern::Tuple(parse_patterns(words, names, true)?),
+            "_" => MatchPattern::Unused,
+            word if word.contains('@') => MatchPattern::Var(parse_var(word, names)?),
+            word => MatchPattern::Val(parse_literal(word)?),
+        });
+    }
+    match is_nested {
+        true => Err("unclosed tuple pattern".to_string()),
+        false => Ok(patterns),
+    }
+}
+/// A pattern constant: a quoted string, `true`, `false`, or a number, which
+/// is a float if it has a point or exponent.
+fn parse_literal(word: &str) -> Result<VarV, String> {
+    if word.starts_with('"') {
+        return Ok(VarV::Str(unquote(word)?));
+    }
+    match word {
+        "true" => return Ok(VarV::Bool(true)),
+        "false" => return Ok(VarV::Bool(false)),
+        _ => (),
+    }
+    let is_float = word.contains(['.', 'e', 'E']) || word.ends_with("inf") || word == "NaN";
+    if is_float {
+        return word.parse().map(VarV::Float).map_err(|_| format!("invalid number {:?}", word));
+    }
+    match word.parse::<isize>() {
+        Ok(n) => Ok(VarV::Num(n)),
+        #[cfg(feature = "bigint")]
+        Err(_) => word.parse().map(VarV::Big).map_err(|_| format!("invalid literal {:?}", word)),
+        #[cfg(not(feature = "bigint"))]
+        Err(_) => Err(format!("invalid literal {:?}", word)),
+    }
+}
+/// Splits a line into words, keeping quoted strings whole and dropping a
+/// `;` comment.
+fn split(line: &str) -> Result<Vec<String>, String> {
+    let mut words: Vec<String> = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == ';' {
+            break;
+        } else if c == '"' {
+            let mut word = String::from(chars.next().unwrap_or('"'));
+            let mut is_closed = false;
+            while let Some(c) = chars.next() {
+                word.push(c);
+                match c {
+                    '\\' => word.extend(chars.next()),
+                    '"' => {
+                        is_closed = true;
+                        break;
+                    }
+                    _ => (),
+                }
+            }
+            if !is_closed {
+                return Err("unterminated string".to_string());
+            }
+            words.push(word);
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == ';' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            words.push(word);
+        }
+    }
+    Ok(words)
+}
+/// The contents of a string written with Rust's escapes, as `{:?}` prints.
+fn unquote(word: &str) -> Result<String, String> {
+    let inner = word.strip_prefix('"').and_then(|word| word.strip_suffix('"'));
+    let inner = inner.ok_or(format!("invalid string {}", word))?;
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '"' | '\'')) => c,
+            Some('u') => {
+                let code: String = chars.by_ref().skip(1).take_while(|c| *c != '}').collect();
+                u32::from_str_radix(&code, 16).ok().and_then(char::from_u32)
+                    .ok_or(format!("invalid escape in {}", word))?
+            }
+            _ => return Err(format!("invalid escape in {}", word)),
+        });
+    }
+    Ok(out)
+}
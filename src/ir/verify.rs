@@ -0,0 +1,396 @@
+//! Static checks on a lowered program: every jump lands inside it, blocks
+//! are entered and left in pairs, every variable is a slot of a frame that
+//! is open, and no instruction can take more values than the stack holds.
+//! The frame the code starts in, the caller's or a function's, grows as
+//! needed, up to `MAX_SLOTS`; a block's frame has the slots it is entered
+//! with.
+//!
+//! Statements may leave values behind, so paths that meet can carry
+//! different stack depths, as after an `if` without `else` whose branch
+//! leaves a value. Past such a join the depth is only known to lie between
+//! the smallest and the largest; taking more than the smallest is left to
+//! the VM, since whether the values are there depends on the path taken,
+//! but taking more than the largest is an error. A block that may end with
+//! a single value leaves an unknown number, since a tuple spreads into its
+//! elements, and a loop whose depth keeps growing has no largest depth.
+use std::fmt;
+
+use super::text::Line;
+use super::{MatchPattern, IR};
+use crate::types::Var;
+use crate::vm::MAX_SLOTS;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    InvalidTarget(usize),
+    /// Takes more values than any path leaves on the stack.
+    StackUnderflow { needed: usize, depth: usize },
+    /// `Leave` with no block open.
+    NoBlock,
+    UnwindTooDeep { depth: usize, open: usize },
+    /// Two paths reach an instruction inside different numbers of blocks.
+    InconsistentBlocks { expected: usize, found: usize },
+    /// The program ends while blocks are still open.
+    UnclosedBlocks(usize),
+    /// `Enter` with more than `MAX_SLOTS` slots.
+    TooManySlots(usize),
+    /// A variable in a frame further out than any that is open.
+    NoFrame { depth: usize, open: usize },
+    /// A variable past the end of its block's frame.
+    NoSlot { slot: usize, slots: usize },
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub index: usize,
+    /// The instruction as listed by `disassemble`, if `index` is one.
+    pub instruction: Option<String>,
+    pub problem: Problem,
+}
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::InvalidTarget(target) => write!(f, "jumps to {}, past the end of the program", target),
+            Problem::StackUnderflow { needed, depth } => {
+                write!(f, "takes {} values but the stack holds at most {}", needed, depth)
+            }
+            Problem::NoBlock => write!(f, "leaves a block when none is open"),
+            Problem::UnwindTooDeep { depth, open } => {
+                write!(f, "unwinds {} blocks but only {} are open", depth, open)
+            }
+            Problem::InconsistentBlocks { expected, found } => {
+                write!(f, "is reached inside {} blocks on one path and {} on another", expected, found)
+            }
+            Problem::UnclosedBlocks(open) => write!(f, "the program ends with {} blocks open", open),
+            Problem::TooManySlots(slots) => {
+                write!(f, "opens a frame of {} slots, more than the limit of {}", slots, MAX_SLOTS)
+            }
+            Problem::NoFrame { depth, open } => {
+                write!(f, "uses a frame {} out but only {} frames are open", depth, open)
+            }
+            Problem::NoSlot { slot, slots } => write!(f, "uses slot {} of a frame with {} slots", slot, slots),
+        }
+    }
+}
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.instruction {
+            Some(instruction) => write!(f, "instruction {} `{}`: {}", self.index, instruction, self.problem),
+            None => write!(f, "at the end: {}", self.problem),
+        }
+    }
+}
+
+/// The number of values on one block's stack: between `min` and `max`,
+/// which is `UNBOUNDED` if no limit is known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Depth {
+    min: usize,
+    max: usize,
+}
+const UNBOUNDED: usize = usize::MAX;
+impl Depth {
+    const EMPTY: Depth = Depth { min: 0, max: 0 };
+    fn join(self, other: Depth) -> Depth {
+        Depth { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+    /// `join`, but giving up on the largest depth if it grows, so that
+    /// going round a loop again cannot raise it forever.
+    fn widen(self, other: Depth) -> Depth {
+        let joined = self.join(other);
+        match joined.max > self.max {
+            true => Depth { max: UNBOUNDED, ..joined },
+            false => joined,
+        }
+    }
+    /// The values of both stacks on one.
+    fn add(self, other: Depth) -> Depth {
+        let max = match self.max == UNBOUNDED || other.max == UNBOUNDED {
+            true => UNBOUNDED,
+            false => self.max + other.max,
+        };
+        Depth { min: self.min + other.min, max }
+    }
+}
+/// What is known on entry to an instruction.
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    stack: Depth,
+    /// The stacks of the enclosing blocks, innermost last.
+    blocks: Vec<Depth>,
+    /// The slots of the frame each open block entered, innermost last.
+    frames: Vec<usize>,
+}
+impl State {
+    /// Checks that `var` is a slot of an open frame.
+    fn access(&self, var: Var) -> Result<(), Problem> {
+        let open = self.frames.len();
+        let slots = match open.checked_sub(var.depth + 1) {
+            Some(index) => self.frames[index],
+            // the frame the code started in
+            None if var.depth == open => MAX_SLOTS,
+            None => return Err(Problem::NoFrame { depth: var.depth, open: open + 1 }),
+        };
+        if var.slot >= slots {
+            return Err(Problem::NoSlot { slot: var.slot, slots });
+        }
+        Ok(())
+    }
+    fn pop(&mut self, needed: usize) -> Result<(), Problem> {
+        let Depth { min, max } = self.stack;
+        if max < needed {
+            return Err(Problem::StackUnderflow { needed, depth: max });
+        }
+        let max = if max == UNBOUNDED { max } else { max - needed };
+        self.stack = Depth { min: min.saturating_sub(needed), max };
+        Ok(())
+    }
+    /// Takes `popped` values and pushes `pushed`.
+    fn apply(mut self, popped: usize, pushed: usize) -> Result<State, Problem> {
+        self.pop(popped)?;
+        self.stack = self.stack.add(Depth { min: pushed, max: pushed });
+        Ok(self)
+    }
+}
+
+/// Checks `code` from its start and from every function entry.
+pub fn verify(code: &[IR]) -> Result<(), VerifyError> {
+    let mut states: Vec<Option<State>> = vec![None; code.len() + 1];
+    let mut pending: Vec<usize> = Vec::new();
+    let roots = code.iter().filter_map(|instruction| match instruction {
+        IR::Function(_, _, entry) => Some(*entry),
+        _ => None,
+    });
+    for root in std::iter::once(0).chain(roots) {
+        let state = State { stack: Depth::EMPTY, blocks: Vec::new(), frames: Vec::new() };
+        merge(&mut states, &mut pending, root, state, false, code)?;
+    }
+    // an instruction is only judged once every path into it has been
+    // joined, so problems wait for the second loop
+    while let Some(index) = pending.pop() {
+        let Some(state) = states[index].clone().filter(|_| index < code.len()) else {
+            continue;
+        };
+        for (target, state) in successors(index, &code[index], state).unwrap_or_default() {
+            if target <= code.len() {
+                merge(&mut states, &mut pending, target, state, target <= index, code)?;
+            }
+        }
+    }
+    for (index, state) in states.into_iter().enumerate() {
+        let Some(state) = state else {
+            continue;
+        };
+        if index == code.len() {
+            if !state.blocks.is_empty() {
+                return Err(VerifyError { index, instruction: None, problem: Problem::UnclosedBlocks(state.blocks.len()) });
+            }
+            continue;
+        }
+        let fail = |problem: Problem| VerifyError { index, instruction: Some(Line(&code[index], &[]).to_string()), problem };
+        for (target, _) in successors(index, &code[index], state).map_err(fail)? {
+            if target > code.len() {
+                return Err(fail(Problem::InvalidTarget(target)));
+            }
+        }
+    }
+    Ok(())
+}
+/// Joins `state` into what is known at `index`, queueing it if that
+/// changed. Every loop has a jump back, so widening where one lands is
+/// enough for the depths to settle.
+fn merge(
+    states: &mut [Option<State>],
+    pending: &mut Vec<usize>,
+    index: usize,
+    state: State,
+    is_back: bool,
+    code: &[IR],
+) -> Result<(), VerifyError> {
+    let join = if is_back { Depth::widen } else { Depth::join };
+    let known = match &states[index] {
+        None => state,
+        Some(known) if known.blocks.len() != state.blocks.len() => {
+            let problem = Problem::InconsistentBlocks { expected: known.blocks.len(), found: state.blocks.len() };
+            let instruction = code.get(index).map(|instruction| Line(instruction, &[]).to_string());
+            return Err(VerifyError { index, instruction, problem });
+        }
+        Some(known) => State {
+            stack: join(known.stack, state.stack),
+            blocks: known.blocks.iter().zip(&state.blocks).map(|(a, b)| join(*a, *b)).collect(),
+            frames: known.frames.iter().zip(&state.frames).map(|(a, b)| *a.min(b)).collect(),
+        },
+    };
+    if states[index].as_ref() != Some(&known) {
+        states[index] = Some(known);
+        pending.push(index);
+    }
+    Ok(())
+}
+/// Where control can go after the instruction at `index`, and with what.
+fn successors(index: usize, instruction: &IR, state: State) -> Result<Vec<(usize, State)>, Problem> {
+    let next = index + 1;
+    let flow = match instruction {
+        IR::Num(_) | IR::Float(_) | IR::Bool(_) | IR::Str(_) | IR::Input(_) => vec![(next, state.apply(0, 1)?)],
+        IR::Load(var) => {
+            state.access(*var)?;
+            vec![(next, state.apply(0, 1)?)]
+        }
+        #[cfg(feature = "bigint")]
+        IR::BigNum(_) => vec![(next, state.apply(0, 1)?)],
+        IR::Nil | IR::Function(..) => vec![(next, state)],
+        IR::Not | IR::Len => vec![(next, state.apply(1, 1)?)],
+        IR::BinExpr(_) | IR::Or | IR::And | IR::Eql | IR::NEql | IR::Ls | IR::Gt | IR::LsEql | IR::GtEql
+        | IR::Index | IR::Range => vec![(next, state.apply(2, 1)?)],
+        IR::Store(var) => {
+            state.access(*var)?;
+            vec![(next, state.apply(1, 0)?)]
+        }
+        IR::Output(_) => vec![(next, state.apply(1, 0)?)],
+        IR::List(len) => vec![(next, state.apply(*len, 1)?)],
+        IR::Slice(has_start, has_end) => vec![(next, state.apply(1 + *has_start as usize + *has_end as usize, 1)?)],
+        IR::SetItem(depth) => vec![(next, state.apply(depth + 2, 1)?)],
+        IR::Call(_, argc) => vec![(next, state.apply(*argc, 1)?)],
+        IR::Jump(target) => vec![(*target, state)],
+        // the value stays when the jump is taken
+        IR::Test(_, target) => {
+            let taken = state.clone().apply(1, 1)?;
+            vec![(*target, taken), (next, state.apply(1, 0)?)]
+        }
+        IR::Case(patterns, target) => {
+            for var in patterns.iter().flat_map(MatchPattern::bindings) {
+                state.access(var)?;
+            }
+            let state = state.apply(patterns.len(), 0)?;
+            vec![(*target, state.clone()), (next, state)]
+        }
+        IR::Enter(slots) => {
+            if *slots > MAX_SLOTS {
+                return Err(Problem::TooManySlots(*slots));
+            }
+            let mut state = state;
+            state.blocks.push(state.stack);
+            state.frames.push(*slots);
+            state.stack = Depth::EMPTY;
+            vec![(next, state)]
+        }
+        IR::Leave => {
+            let mut state = state;
+            let outer = state.blocks.pop().ok_or(Problem::NoBlock)?;
+            state.frames.pop();
+            let Depth { min, max } = state.stack;
+            let values = if min <= 1 && 1 <= max { Depth { min: 0, max: UNBOUNDED } } else { state.stack };
+            state.stack = outer.add(values);
+            vec![(next, state)]
+        }
+        IR::Unwind(depth, target) => {
+            let mut state = state;
+            if state.blocks.len() < *depth {
+                return Err(Problem::UnwindTooDeep { depth: *depth, open: state.blocks.len() });
+            }
+            for _ in 0..*depth {
+                state.stack = state.blocks.pop().unwrap_or(state.stack);
+                state.frames.pop();
+            }
+            vec![(*target, state)]
+        }
+        IR::LoadBin(left, right, _) => {
+            state.access(*left)?;
+            state.access(*right)?;
+            vec![(next, state.apply(0, 1)?)]
+        }
+        IR::BinStore(_, var) => {
+            state.access(*var)?;
+            vec![(next, state.apply(2, 0)?)]
+        }
+        IR::JumpUnless(_, target) => {
+            let state = state.apply(2, 0)?;
+            vec![(*target, state.clone()), (next, state)]
+        }
+        IR::Ret(has_value) => {
+            state.apply(*has_value as usize, 0)?;
+            Vec::new()
+        }
+        IR::End => Vec::new(),
+    };
+    Ok(flow)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::assemble;
+
+    /// Where `listing` fails verification, and why.
+    fn problem(listing: &str) -> (usize, Problem) {
+        let program = assemble(listing).expect("test listing does not assemble");
+        let error = verify(&program.code).expect_err("test listing verifies");
+        (error.index, error.problem)
+    }
+    #[test]
+    fn rejects_a_target_past_the_end() {
+        let error = verify(&[IR::Num(1), IR::Jump(3)]).expect_err("jump past the end verifies");
+        assert_eq!((error.index, error.problem), (1, Problem::InvalidTarget(3)));
+    }
+    #[test]
+    fn rejects_an_underflow_after_a_join() {
+        let listing = "
+            bool true
+            case false L3
+            num 1
+        L3:
+            add
+        ";
+        assert_eq!(problem(listing), (3, Problem::StackUnderflow { needed: 2, depth: 1 }));
+    }
+    #[test]
+    fn accepts_a_join_that_may_hold_enough() {
+        let listing = "
+            num 1
+            bool true
+            case false L4
+            num 2
+        L4:
+            add
+            output
+        ";
+        assert_eq!(verify(&assemble(listing).expect("test listing does not assemble").code), Ok(()));
+    }
+    #[test]
+    fn rejects_leaving_no_block() {
+        assert_eq!(problem("leave"), (0, Problem::NoBlock));
+        assert_eq!(problem("unwind 1 L1\nL1:"), (0, Problem::UnwindTooDeep { depth: 1, open: 0 }));
+    }
+    #[test]
+    fn rejects_unwinding_past_the_open_blocks() {
+        let listing = "
+            enter 0
+                unwind 2 L3
+            leave
+        L3:
+        ";
+        assert_eq!(problem(listing), (1, Problem::UnwindTooDeep { depth: 2, open: 1 }));
+    }
+    #[test]
+    fn rejects_a_slot_past_the_frame() {
+        let listing = "
+            enter 1
+                num 1
+                store x@0.1
+            leave
+        ";
+        assert_eq!(problem(listing), (2, Problem::NoSlot { slot: 1, slots: 1 }));
+    }
+    #[test]
+    fn rejects_a_frame_that_is_not_open() {
+        let listing = "
+            enter 1
+                load x@2.0
+                output
+            leave
+        ";
+        assert_eq!(problem(listing), (1, Problem::NoFrame { depth: 2, open: 2 }));
+    }
+    #[test]
+    fn rejects_an_oversized_frame() {
+        assert_eq!(problem("enter 65537\nleave"), (0, Problem::TooManySlots(MAX_SLOTS + 1)));
+    }
+}
@@ -1,39 +1,130 @@
 use std::collections::HashMap;
 
+use crate::diagnostic::{Diagnostic, Span};
+use crate::inout::{Comments, Vocabulary};
+
 mod token;
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Token {
     Brackets { id: u8, is_opened: bool },
     Sign(u8),
     Bool(bool),
     Number(isize),
+    Float(f64),
+    /// Integer literal too large for `isize`.
+    #[cfg(feature = "bigint")]
+    BigNumber(num_bigint::BigInt),
+    Str(String),
     Name(usize),
     Mark(u8),
     Comparsion(u8),
     Dot(bool),
     EOF,
 }
-pub fn tokenize_code(eq: String, keywords: &HashMap<String,u8>) -> Vec<Token> {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexeme {
+    pub token: Token,
+    pub span: Span,
+}
+/// Source comment, kept for tools that need more than the token stream.
+#[derive(Debug, Clone, PartialEq)]
+#[allow(dead_code)]
+pub struct Comment {
+    pub text: String,
+    pub span: Span,
+    pub is_block: bool,
+}
+/// Splits source code into tokens. Comments are skipped, or collected into
+/// `comments` when it is given. `names`, when given, receives every name by
+/// the id its `Token::Name` carries.
+pub fn tokenize_code(
+    eq: String,
+    vocabulary: &Vocabulary,
+    mut comments: Option<&mut Vec<Comment>>,
+    names_out: Option<&mut Vec<String>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Lexeme> {
     let chars: Vec<char> = eq.chars().collect();
+    let positions: Vec<Span> = locate(&chars);
     let mut index: usize = 0;
-    let mut tokens: Vec<Token> = Vec::new();
+    let mut tokens: Vec<Lexeme> = Vec::new();
     let mut names: HashMap<String, usize> = HashMap::new();
     while chars.len() > index {
+        let start = index;
+        if let Some(comment) = tokenize_comment(&chars, &positions, &mut index, &vocabulary.comments, diagnostics) {
+            if let Some(comments) = comments.as_mut() {
+                comments.push(comment);
+            }
+            continue;
+        }
         match chars[index] {
             '\n' | '\r' | '\t' | ' ' => {index += 1;},
 
-            val if val.is_ascii_punctuation() => tokens.append(&mut tokenize_symbol(&chars, &mut index)),
+            '"' => {
+                let token = tokenize_string(&chars, &positions, &mut index, diagnostics);
+                tokens.push(Lexeme { token, span: span_between(&positions, start, index) });
+            }
+
+            val if val.is_ascii_punctuation() => {
+                tokens.append(&mut tokenize_symbol(&chars, &positions, &mut index, &vocabulary.comments, diagnostics))
+            }
 
-            '0'..='9' => tokens.push(tokenize_number(& chars,&mut index)),
+            '0'..='9' => {
+                let token = tokenize_number(&chars, &mut index);
+                let span = span_between(&positions, start, index);
+                match token {
+                    Some(token) => tokens.push(Lexeme { token, span }),
+                    None => diagnostics.push(Diagnostic::error(
+                        format!("integer literal is too large, the maximum is {}", isize::MAX),
+                        span,
+                    )),
+                }
+            }
 
-            '_' | 'A'..='Z' | 'a'..='z' => tokens.push(tokenize_name(&chars,&mut index,&mut names, keywords)),
-            _ => println!("symbol not recognized: {}", chars.last().unwrap()),
+            '_' | 'A'..='Z' | 'a'..='z' => {
+                let token = tokenize_name(&chars, &mut index, &mut names, &vocabulary.keywords);
+                tokens.push(Lexeme { token, span: span_between(&positions, start, index) });
+            }
+            val => {
+                index += 1;
+                diagnostics.push(Diagnostic::error(
+                    format!("symbol not recognized: {:?}", val),
+                    span_between(&positions, start, index),
+                ));
+            }
+        }
+    }
+    tokens.push(Lexeme { token: Token::EOF, span: span_between(&positions, index, index) });
+    if let Some(names_out) = names_out {
+        *names_out = vec![String::new(); names.len()];
+        for (name, id) in names {
+            names_out[id] = name;
         }
     }
-    tokens.push(Token::EOF);
     tokens
 }
-fn tokenize_name(chars: &Vec<char>,index: &mut usize, names: &mut HashMap<String,usize>, keywords: &HashMap<String,u8>) -> Token {
+/// Start position of every char, plus one extra entry for the end of input.
+fn locate(chars: &[char]) -> Vec<Span> {
+    let mut positions: Vec<Span> = Vec::with_capacity(chars.len() + 1);
+    let (mut offset, mut line, mut column) = (0, 1, 1);
+    for c in chars {
+        positions.push(Span { start: offset, end: offset, line, column });
+        offset += c.len_utf8();
+        if *c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+    positions.push(Span { start: offset, end: offset, line, column });
+    positions
+}
+fn span_between(positions: &[Span], from: usize, to: usize) -> Span {
+    positions[from].to(positions[to])
+}
+fn tokenize_name(chars: &[char],index: &mut usize, names: &mut HashMap<String,usize>, keywords: &HashMap<String,u8>) -> Token {
     let mut name: Box<str> = Box::from("");
     loop {
         if chars.len() > *index {
@@ -50,7 +141,7 @@ fn tokenize_name(chars: &Vec<char>,index: &mut usize, names: &mut HashMap<String
     match keywords.get(&name.to_string()) {
         Some(val) => Token::Mark(*val),
         None => {match names.get(&name.to_string()) {
-            Some(id) => Token::Name(id.clone()),
+            Some(id) => Token::Name(*id),
             None => {
                 names.insert(name.to_string(), names.len());
                 Token::Name(names.len() - 1)
@@ -58,14 +149,16 @@ fn tokenize_name(chars: &Vec<char>,index: &mut usize, names: &mut HashMap<String
         }
     }
 }
-fn tokenize_number(chars: &Vec<char>, index: &mut usize) -> Token {
+/// Returns `None` when an integer literal does not fit into `isize` (only
+/// without the `bigint` feature).
+fn tokenize_number(chars: &[char], index: &mut usize) -> Option<Token> {
     let mut number: String = String::new();
     if chars.len() == *index {
-        return Token::Number(0);
+        return Some(Token::Number(0));
     }
     loop {
         if chars.len() > *index {
-            if !chars[*index].is_numeric() {
+            if !chars[*index].is_ascii_digit() {
                 break;
             }
         } else {
@@ -75,28 +168,179 @@ fn tokenize_number(chars: &Vec<char>, index: &mut usize) -> Token {
         *index += 1;
         number.push(i);
     }
-    Token::Number(number.parse().unwrap())
+    // a dot directly followed by a digit is a decimal point, otherwise it
+    // terminates the statement
+    if chars.len() > *index + 1 && chars[*index] == '.' && chars[*index + 1].is_ascii_digit() {
+        *index += 1;
+        number.push('.');
+        while chars.len() > *index && chars[*index].is_ascii_digit() {
+            number.push(chars[*index]);
+            *index += 1;
+        }
+        return Some(Token::Float(number.parse().unwrap()));
+    }
+    match number.parse() {
+        Ok(val) => Some(Token::Number(val)),
+        #[cfg(feature = "bigint")]
+        Err(_) => number.parse().ok().map(Token::BigNumber),
+        #[cfg(not(feature = "bigint"))]
+        Err(_) => None,
+    }
+}
+/// Reads a double-quoted string literal, resolving `\n`, `\t`, `\r`, `\0`,
+/// `\\` and `\"` escapes.
+fn tokenize_string(chars: &[char], positions: &[Span], index: &mut usize, diagnostics: &mut Vec<Diagnostic>) -> Token {
+    let start = *index;
+    let mut string: String = String::new();
+    *index += 1;
+    loop {
+        if chars.len() <= *index {
+            diagnostics.push(Diagnostic::error(
+                "unterminated string literal",
+                span_between(positions, start, *index),
+            ));
+            break;
+        }
+        let i = chars[*index];
+        *index += 1;
+        match i {
+            '"' => break,
+            '\\' if chars.len() > *index => {
+                let escaped = chars[*index];
+                *index += 1;
+                string.push(match escaped {
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    '0' => '\0',
+                    '\\' | '"' => escaped,
+                    _ => {
+                        diagnostics.push(Diagnostic::error(
+                            format!("unknown escape sequence: \\{}", escaped),
+                            span_between(positions, *index - 2, *index),
+                        ));
+                        escaped
+                    }
+                });
+            }
+            _ => string.push(i),
+        }
+    }
+    Token::Str(string)
+}
+fn starts_with(chars: &[char], index: usize, pattern: &str) -> bool {
+    !pattern.is_empty() && chars.len() >= index + pattern.chars().count()
+        && pattern.chars().zip(&chars[index..]).all(|(p, c)| p == *c)
+}
+fn is_comment_start(chars: &[char], index: usize, delimiters: &Comments) -> bool {
+    delimiters.line.as_ref().is_some_and(|line| starts_with(chars, index, line))
+        || delimiters.block.as_ref().is_some_and(|(open, _)| starts_with(chars, index, open))
+}
+/// Reads a line comment up to the end of the line, or a block comment up to
+/// its matching closing delimiter. Block comments nest.
+fn tokenize_comment(
+    chars: &[char],
+    positions: &[Span],
+    index: &mut usize,
+    delimiters: &Comments,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<Comment> {
+    let start = *index;
+    if let Some((open, close)) = &delimiters.block {
+        if starts_with(chars, *index, open) {
+            let (open_len, close_len) = (open.chars().count(), close.chars().count());
+            *index += open_len;
+            let mut depth: usize = 1;
+            while depth > 0 {
+                if chars.len() <= *index {
+                    diagnostics.push(Diagnostic::error(
+                        "unterminated block comment",
+                        span_between(positions, start, start + open_len),
+                    ));
+                    break;
+                } else if starts_with(chars, *index, close) {
+                    depth -= 1;
+                    *index += close_len;
+                } else if starts_with(chars, *index, open) {
+                    depth += 1;
+                    *index += open_len;
+                } else {
+                    *index += 1;
+                }
+            }
+            let text_end = if depth == 0 { *index - close_len } else { *index };
+            return Some(Comment {
+                text: chars[start + open_len..text_end].iter().collect(),
+                span: span_between(positions, start, *index),
+                is_block: true,
+            });
+        }
+    }
+    if let Some(line) = &delimiters.line {
+        if starts_with(chars, *index, line) {
+            *index += line.chars().count();
+            let text_start = *index;
+            while chars.len() > *index && chars[*index] != '\n' {
+                *index += 1;
+            }
+            return Some(Comment {
+                text: chars[text_start..*index].iter().collect(),
+                span: span_between(positions, start, *index),
+                is_block: false,
+            });
+        }
+    }
+    None
 }
-fn tokenize_symbol(chars: &Vec<char>,index: &mut usize) -> Vec<Token> {
-    let mut tokens: Vec<Token> = Vec::new();
+fn tokenize_symbol(
+    chars: &[char],
+    positions: &[Span],
+    index: &mut usize,
+    delimiters: &Comments,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<Lexeme> {
+    let mut tokens: Vec<Lexeme> = Vec::new();
     let mut symbol_sequence:String = String::new();
+    let start = *index;
     while chars.len() > *index {
-        if !chars[*index].is_ascii_punctuation()
+        if !chars[*index].is_ascii_punctuation() || chars[*index] == '"'
+            || is_comment_start(chars, *index, delimiters)
         {
             break;
         }
         let i = chars[*index];
+        // brackets and colons are always tokens of their own, so `[[`, `))`
+        // or `_:` need no spaces
+        let is_single = matches!(i, '(' | ')' | '[' | ']' | '{' | '}' | ':');
+        if is_single && !symbol_sequence.is_empty() {
+            break;
+        }
+        // `..` is the range operator rather than two terminators
+        if i == '.' && chars.get(*index + 1) == Some(&'.') {
+            if symbol_sequence.is_empty() {
+                symbol_sequence.push_str("..");
+                *index += 2;
+            }
+            break;
+        }
         *index += 1;
         if let ',' | '.' = i {
-            tokens.push(Token::Dot(i == ','));
-            if symbol_sequence.len() == 0 {
+            tokens.push(Lexeme {
+                token: Token::Dot(i == ','),
+                span: span_between(positions, *index - 1, *index),
+            });
+            if symbol_sequence.is_empty() {
                 return tokens;
             }
             break;
         }
         symbol_sequence.push(i);
+        if is_single {
+            break;
+        }
     }
-    tokens.push(match symbol_sequence.as_str() {
+    let span = span_between(positions, start, start + symbol_sequence.chars().count());
+    let token = match symbol_sequence.as_str() {
 
         "(" => Token::Brackets {id: 1,  is_opened: true},
         ")" => Token::Brackets {id: 1, is_opened: false},
@@ -106,52 +350,58 @@ fn tokenize_symbol(chars: &Vec<char>,index: &mut usize) -> Vec<Token> {
         "}" => Token::Brackets {id: 3, is_opened: false},
         "<" => Token::Brackets { id: 4, is_opened: true },
         ">" => Token::Brackets { id: 4, is_opened: false },
-        
+
         "!!" => Token::Bool(false),
         "==" => Token::Bool(true),
-        
+
         "~" => Token::Mark(0),
         "!" => Token::Mark(1),
         "@" => Token::Mark(2),
+        "_" => Token::Mark(10),
         "#" => Token::Mark(3),
         "$" => Token::Mark(4),
+        ":" => Token::Mark(5),
         "^" => Token::Mark(6),
         "&" => Token::Mark(7),
         "?" => Token::Mark(8),
         "|" => Token::Mark(9),
-        
-        "!:" => Token::Mark(11),
+
         "!-" => Token::Mark(12),
         "!+" => Token::Mark(13),
-        
+
         "--" => Token::Mark(14),
+        ".." => Token::Mark(24),
         "=>" => Token::Mark(15),
         "->" => Token::Mark(16),
-        
+
         "|+" => Token::Mark(17),
         "|-" => Token::Mark(18),
         "~+" => Token::Mark(19),
         "~-" => Token::Mark(20),
         "@+" => Token::Mark(21),
         "@-" => Token::Mark(22),
-        
+
         "=" => Token::Comparsion(1),
         ">>" => Token::Comparsion(2),
         "<<" => Token::Comparsion(3),
         "!=" | "=!" => Token::Comparsion(4),
         ">=" => Token::Comparsion(5),
         "<=" => Token::Comparsion(6),
-        
+
         "+" => Token::Sign(1),
         "-" => Token::Sign(2),
         "*" => Token::Sign(3),
         "/" => Token::Sign(4),
         "%" => Token::Sign(5),
-        
-        _ => panic!(
-            "unexpected symbol sequence: \"{}\"",
-            symbol_sequence
-        ),
-    });
-    tokens.iter().rev().cloned().collect()
+
+        _ => {
+            diagnostics.push(Diagnostic::error(
+                format!("unexpected symbol sequence: \"{}\"", symbol_sequence),
+                span,
+            ));
+            return tokens;
+        }
+    };
+    tokens.push(Lexeme { token, span });
+    tokens.into_iter().rev().collect()
 }
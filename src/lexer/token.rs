@@ -5,20 +5,17 @@ use super::Token;
 impl Token {
     pub fn is_operation(&self) -> bool {
         //print!("{:?}", self);
-        match &self {
-            Token::Mark(1 | 7 | 9) | Token::Comparsion(_) | Token::Sign(_) => true,
-            _ => false,
-        }
+        matches!(self, Token::Mark(1 | 7 | 9 | 24) | Token::Comparsion(_) | Token::Sign(_))
     }
-    pub fn name_id(&self) -> usize {
+    pub fn name_id(&self) -> Option<usize> {
         match &self {
-            Token::Name(id) => *id,
-            _ => panic!("expected name token"),
+            Token::Name(id) => Some(*id),
+            _ => None,
         }
     }
     pub fn get_operation_priorety(&self) -> u8 {
         match &self {
-            Token::Comparsion(_) => 4,
+            Token::Comparsion(_) | Token::Mark(24) => 4,
             Token::Sign(1..=2) => 5,
             Token::Sign(3..=5) => 6,
             Token::Mark(7) => 2,
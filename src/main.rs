@@ -1,27 +1,278 @@
+mod bytecode;
+mod diagnostic;
+mod differential;
+mod fold;
 mod inout;
+mod interp;
 mod ir;
 mod lexer;
 mod parser;
 mod vm;
 mod types;
-use vm::VarV;
+use diagnostic::{Diagnostic, Source};
 use inout::{read_json, Vocabulary};
 use parser::parse_program;
 use types::FlowListener;
 use std::cell::RefCell;
-use std::collections::HashMap;
 use std::fs;
 use std::rc::Rc;
+const VM_STACK_SIZE: usize = 64 * 1024 * 1024;
+/// Which engine runs the program.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Vm,
+    /// The tree-walking interpreter in `interp`.
+    Interpret,
+    /// Every program in `code/` through both, compared.
+    Differential,
+    /// The VM timed over repeated runs, without printing, on the program
+    /// lowered both without `-O` and with it.
+    Bench,
+    /// Save the lowered program as `.fqc` instead of running it.
+    Compile,
+    /// Run a `.fqc` file saved by `Compile`.
+    Run,
+    /// Print the lowered program as text instead of running it.
+    Disassemble,
+    /// Run a hand-written `.fqa` listing.
+    Assemble,
+    /// Print the control flow graph of the lowered program as DOT.
+    Cfg,
+    /// Print the syntax tree as DOT.
+    Tree,
+}
+/// Runs averaged over by `--bench`.
+const BENCH_RUNS: u32 = 20;
 fn main() {
+    let mut options = vm::Options::default();
+    let mut mode = Mode::Vm;
+    let mut optimize = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--overflow=trap" => options.overflow = vm::Overflow::Trap,
+            "--overflow=wrap" => options.overflow = vm::Overflow::Wrap,
+            "--overflow=saturate" => options.overflow = vm::Overflow::Saturate,
+            #[cfg(feature = "bigint")]
+            "--overflow=promote" => options.overflow = vm::Overflow::Promote,
+            "--interpret" => mode = Mode::Interpret,
+            "--differential" => mode = Mode::Differential,
+            "--bench" => mode = Mode::Bench,
+            "--compile" => mode = Mode::Compile,
+            "--run" => mode = Mode::Run,
+            "--disassemble" => mode = Mode::Disassemble,
+            "--assemble" => mode = Mode::Assemble,
+            "--cfg" => mode = Mode::Cfg,
+            "--tree" => mode = Mode::Tree,
+            "-O" => optimize = true,
+            _ => {
+                eprintln!("unknown argument: {}", arg);
+                std::process::exit(2);
+            }
+        }
+    }
+    // nested blocks and calls recurse in the parser and the interpreter,
+    // so give them room for vm::MAX_CALL_DEPTH calls
+    let main_thread = std::thread::Builder::new()
+        .stack_size(VM_STACK_SIZE)
+        .spawn(move || run(mode, options, optimize))
+        .expect("cannot start vm thread");
+    let code = main_thread.join().expect("vm thread panicked");
+    if code != 0 {
+        std::process::exit(code);
+    }
+}
+/// Runs the program named on stdin in `mode`, folding constants first and
+/// rewriting the lowered code with `ir::peephole` if `optimize` is set.
+fn run(mode: Mode, options: vm::Options, optimize: bool) -> i32 {
+    if mode == Mode::Differential {
+        let vocabulary: Vocabulary = read_json("vocabulary.json".to_string());
+        return if differential::check_all("code", &vocabulary, options, optimize) { 0 } else { 1 };
+    }
     let mut input: String = "".to_string() ;
     std::io::stdin().read_line(&mut input).expect("cannot read filename");
-    let file = fs::read_to_string(format!("code/{}.fq", input.trim())).expect("cannot read file");
+    if mode == Mode::Run {
+        return run_compiled(&format!("code/{}.fqc", input.trim()), options);
+    }
+    if mode == Mode::Assemble {
+        return run_listing(&format!("code/{}.fqa", input.trim()), options);
+    }
     let vocabulary: Vocabulary = read_json("vocabulary.json".to_string());
-    let mut tokens: Vec<lexer::Token> = lexer::tokenize_code(file, &vocabulary.keywords);
-    let tree: Rc<types::Statement> = Rc::new(parse_program(&mut tokens, &RefCell::new(FlowListener::Console)));
-    let mut ir: Vec<ir::IR> = vec![];
-    ir::ast_to_ir(&tree, &mut ir);
-    let mut env: HashMap<usize, VarV> = HashMap::new();
+    let name = format!("code/{}.fq", input.trim());
+    let file = fs::read_to_string(&name).expect("cannot read file");
+    let source = Source { name, text: file.clone() };
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+    let mut names: Vec<String> = vec![];
+    let tokens: Vec<lexer::Lexeme> = lexer::tokenize_code(file, &vocabulary, None, Some(&mut names), &mut diagnostics);
+    if !diagnostics.is_empty() {
+        for diagnostic in &diagnostics {
+            eprintln!("{}\n", diagnostic.render(&source));
+        }
+        return 1;
+    }
+    let listener = match mode {
+        Mode::Bench => FlowListener::Capture(Rc::default()),
+        _ => FlowListener::Console,
+    };
+    let parsed = parse_program(&tokens, &mut diagnostics, &RefCell::new(listener));
+    for warning in &diagnostics {
+        eprintln!("{}\n", warning.render(&source));
+    }
+    let tree: Rc<types::Statement> = match parsed {
+        Ok(tree) if mode == Mode::Bench => return bench(&tree, &source.name, &names, options),
+        Ok(mut tree) => {
+            if optimize {
+                fold::fold(&mut tree, options);
+            }
+            Rc::new(tree)
+        }
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("{}\n", Diagnostic::from(error).render(&source));
+            }
+            return 1;
+        }
+    };
+    if mode == Mode::Tree {
+        print!("{}", inout::tree_dot(&tree, &names));
+        return 0;
+    }
+    if mode == Mode::Interpret {
+        println!("output: ");
+        let result = interp::Interpreter::new(options).execute(std::slice::from_ref(&*tree), &mut Vec::new());
+        if let Err(error) = result {
+            eprintln!("{}", error.named(&names));
+            return 1;
+        }
+        return 0;
+    }
+    let code = match lower(&tree, optimize) {
+        Ok(code) => code,
+        Err(error) => {
+            eprintln!("{}: {}", source.name, error);
+            return 1;
+        }
+    };
+    if matches!(mode, Mode::Compile | Mode::Disassemble | Mode::Cfg) {
+        let program = ir::Program { code, names };
+        if mode == Mode::Disassemble {
+            print!("{}", ir::disassemble(&program));
+            return 0;
+        }
+        if mode == Mode::Cfg {
+            print!("{}", ir::cfg_dot(&program));
+            return 0;
+        }
+        return compile(&program, &format!("{}c", source.name));
+    }
+    println!("output: ");
+    if let Err(error) = vm::execute(&code, &mut Vec::new(), &mut vm::Runtime::new(options)) {
+        eprintln!("{}", error.named(&names));
+        return 1;
+    }
+    0
+}
+fn lower(tree: &types::Statement, optimize: bool) -> Result<Vec<ir::IR>, ir::VerifyError> {
+    let code = ir::ast_to_ir(std::slice::from_ref(tree))?;
+    if optimize {
+        ir::peephole(code)
+    } else {
+        Ok(code)
+    }
+}
+/// Lowers `tree` without `-O` and with it, and reports the instruction
+/// count and the mean time of `BENCH_RUNS` runs of each.
+fn bench(tree: &types::Statement, name: &str, names: &[String], options: vm::Options) -> i32 {
+    let mut folded = tree.clone();
+    fold::fold(&mut folded, options);
+    let mut means: Vec<std::time::Duration> = Vec::new();
+    for (label, tree, optimize) in [("without -O", tree, false), ("with -O", &folded, true)] {
+        let code = match lower(tree, optimize) {
+            Ok(code) => code,
+            Err(error) => {
+                eprintln!("{}: {}", name, error);
+                return 1;
+            }
+        };
+        let start = std::time::Instant::now();
+        for _ in 0..BENCH_RUNS {
+            if let Err(error) = vm::execute(&code, &mut Vec::new(), &mut vm::Runtime::new(options)) {
+                eprintln!("{}", error.named(names));
+                return 1;
+            }
+        }
+        let mean = start.elapsed() / BENCH_RUNS;
+        println!("{:<10}  {} instructions, {} runs, {:?} per run", label, code.len(), BENCH_RUNS, mean);
+        means.push(mean);
+    }
+    println!("speedup with -O: {:.2}x", means[0].as_secs_f64() / means[1].as_secs_f64());
+    0
+}
+/// Saves `program` to `path` in the `.fqc` format.
+fn compile(program: &ir::Program, path: &str) -> i32 {
+    let bytes = bytecode::encode(program);
+    if let Err(error) = fs::write(path, &bytes) {
+        eprintln!("cannot write {}: {}", path, error);
+        return 1;
+    }
+    println!("wrote {}: {} instructions, {} bytes", path, program.code.len(), bytes.len());
+    0
+}
+/// Assembles a listing in the format `--disassemble` prints, verifies it
+/// and runs it on the VM.
+fn run_listing(path: &str, options: vm::Options) -> i32 {
+    let program = match fs::read_to_string(path) {
+        Ok(text) => ir::assemble(&text),
+        Err(error) => {
+            eprintln!("cannot read {}: {}", path, error);
+            return 1;
+        }
+    };
+    let program = match program {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            return 1;
+        }
+    };
+    if let Err(error) = ir::verify(&program.code) {
+        eprintln!("{}: {}", path, error);
+        return 1;
+    }
+    println!("output: ");
+    // the program's own frame, as `ir::verify` assumes
+    let mut env: Vec<vm::Scope> = vec![vm::Scope::new()];
+    if let Err(error) = vm::execute(&program.code, &mut env, &mut vm::Runtime::new(options)) {
+        eprintln!("{}", error.named(&program.names));
+        return 1;
+    }
+    0
+}
+/// Loads and verifies a program saved by `--compile` and runs it on the VM.
+fn run_compiled(path: &str, options: vm::Options) -> i32 {
+    let program = match fs::read(path) {
+        Ok(bytes) => bytecode::decode(&bytes),
+        Err(error) => {
+            eprintln!("cannot read {}: {}", path, error);
+            return 1;
+        }
+    };
+    let program = match program {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("cannot load {}: {}", path, error);
+            return 1;
+        }
+    };
+    if let Err(error) = ir::verify(&program.code) {
+        eprintln!("cannot load {}: {}", path, error);
+        return 1;
+    }
     println!("output: ");
-    vm::execute(ir.clone(), &mut env);
+    // the program's own frame, as `ir::verify` assumes
+    let mut env: Vec<vm::Scope> = vec![vm::Scope::new()];
+    if let Err(error) = vm::execute(&program.code, &mut env, &mut vm::Runtime::new(options)) {
+        eprintln!("{}", error.named(&program.names));
+        return 1;
+    }
+    0
 }
@@ -1,53 +1,192 @@
-use crate::types::{ActionV,Statement};
+use crate::diagnostic::{Diagnostic, Span};
+use crate::types::{ActionV, Statement, Var};
 use crate::types::{FlowListener, FlowStreamer};
-use core::panic;
-use crate::lexer::Token;
+use crate::lexer::{Lexeme, Token};
 use std::cell::RefCell;
-pub fn parse_program(tokens: &Vec<Token>, listener: &RefCell<FlowListener>) -> Statement {
+use std::mem;
+mod error;
+mod pattern;
+mod scope;
+pub use error::ParseError;
+use scope::Scopes;
+/// Everything the parser accumulates besides the tree: recovered errors,
+/// warnings and the variables visible at the current position.
+#[derive(Default)]
+pub struct ParseState {
+    pub errors: Vec<ParseError>,
+    pub warnings: Vec<Diagnostic>,
+    scopes: Scopes,
+    /// Set for the expression that makes up a whole statement, which may
+    /// leave no value; every other one is an operand.
+    statement: bool,
+}
+/// Parses the whole program; warnings are appended to `warnings` whether or
+/// not parsing succeeds.
+pub fn parse_program(
+    tokens: &[Lexeme],
+    warnings: &mut Vec<Diagnostic>,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, Vec<ParseError>> {
     // println!("parsing program...");
     // println!("tokens: {:?}", tokens);
-    parse_block(tokens, &mut 0, Token::EOF, listener)
+    let mut state = ParseState::default();
+    let result = parse_block(tokens, &mut 0, Token::EOF, None, &mut state, listener);
+    warnings.append(&mut state.warnings);
+    match result {
+        Ok(program) if state.errors.is_empty() => Ok(program),
+        Ok(_) => Err(state.errors),
+        Err(error) => {
+            state.errors.push(error);
+            Err(state.errors)
+        }
+    }
 }
+/// Parses statements up to `closing_brace` in a scope of their own, which
+/// jumps can name by `label`.
 pub fn parse_block(
-    tokens: &Vec<Token>,
+    tokens: &[Lexeme],
     index: &mut usize,
     closing_brace: Token,
+    label: Option<usize>,
+    state: &mut ParseState,
     listener: &RefCell<FlowListener>,
-) -> Statement {
-    let mut statements: Vec<Box<Statement>> = Vec::new();
-    while tokens.len() > *index && tokens[*index] != closing_brace && tokens[*index] != Token::EOF {
-        let stmt: Statement = parse_statement(tokens, index, listener);
-        statements.push(Box::from(stmt));
-    }
-    if tokens.len() == *index || tokens[*index] != closing_brace {
-        panic!("expected closing brace: {:?}, found: {:?}", closing_brace, tokens.get(*index));
+) -> Result<Statement, ParseError> {
+    state.scopes.open(label);
+    let statements = parse_statements(tokens, index, closing_brace, state, listener);
+    state.scopes.close();
+    statements.map(Statement::Block)
+}
+/// Parses statements up to and including `closing_brace` into the current
+/// scope. Errors inside a statement are recorded in `state.errors` and parsing
+/// resumes after the next synchronization point, so the returned error is only
+/// the missing closing brace itself.
+fn parse_statements(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    closing_brace: Token,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Vec<Statement>, ParseError> {
+    let mut statements: Vec<Statement> = Vec::new();
+    loop {
+        let Lexeme { token, span } = tokens[*index].clone();
+        if token == closing_brace {
+            break;
+        }
+        match token {
+            Token::EOF | Token::Brackets { is_opened: false, .. } if closing_brace != Token::EOF => {
+                return Err(ParseError::UnclosedBracket { expected: closing_brace, found: token, span });
+            }
+            _ => match parse_statement(tokens, index, false, state, listener) {
+                Ok(stmt) => statements.push(stmt),
+                Err(error) => {
+                    state.errors.push(error);
+                    synchronize(tokens, index);
+                }
+            },
+        }
     }
     *index += 1;
-    Statement::Block(statements)
+    Ok(statements)
 }
-fn parse_statement(tokens: &Vec<Token>, index: &mut usize, listener: &RefCell<FlowListener>) -> Statement {
+/// Skips tokens up to and including the next statement terminator, or up to a
+/// closing bracket of the enclosing block, stepping over nested brackets.
+fn synchronize(tokens: &[Lexeme], index: &mut usize) {
+    let mut depth: usize = 0;
+    loop {
+        match tokens[*index].token {
+            Token::EOF => return,
+            Token::Brackets { is_opened: true, .. } => depth += 1,
+            Token::Brackets { is_opened: false, .. } if depth == 0 => return,
+            Token::Brackets { is_opened: false, .. } => depth -= 1,
+            Token::Dot(_) if depth == 0 => {
+                *index += 1;
+                return;
+            }
+            _ => (),
+        }
+        *index += 1;
+    }
+}
+/// Parses one statement; `as_value` if it is the operand of `~-` or the
+/// condition of `?` rather than a statement of its own.
+fn parse_statement(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    as_value: bool,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, ParseError> {
+    let start = tokens[*index].span;
     *index += 1;
-    let result = match tokens[*index - 1] {
+    let result = match tokens[*index - 1].token.clone() {
+        Token::Dot(_) => return Ok(Statement::Nil),
         Token::Mark(id) => match id {
-            8 => parse_if_statement(tokens, index, listener),
+            8 => parse_if_statement(tokens, index, state, listener)?,
+            15 => parse_function(tokens, index, state, listener)?,
+            25 => parse_while(tokens, index, None, state, listener)?,
+            26 => parse_for(tokens, index, None, state, listener)?,
+            6 => match tokens[*index].token {
+                Token::Dot(_) | Token::Brackets { is_opened: false, .. } | Token::EOF => Statement::Return(None),
+                _ => Statement::Return(Some(Box::from(parse_expression(tokens, index, 1, state, listener)?))),
+            },
+            // `#` declares the name in the current block, after the value
+            // has been resolved against the enclosing ones; its elements
+            // can only be assigned where it was declared
             3 => {
-                let name = tokens[*index].name_id();
+                let Lexeme { token, span } = tokens[*index].clone();
+                let name = token.name_id().ok_or(ParseError::ExpectedName { found: token, span })?;
+                *index += 1;
+                if is_adjacent_bracket(tokens, *index, 2) {
+                    let var = match state.scopes.local(name) {
+                        Some(var) => var,
+                        None if state.scopes.resolve(name).is_some() => return Err(ParseError::NotLocal { span }),
+                        None => return Err(ParseError::UndefinedName { span }),
+                    };
+                    parse_set_index(tokens, index, var, state, listener)?
+                } else {
+                    let value: Box<Statement> = Box::from(parse_expression(tokens, index, 1, state, listener)?);
+                    let var = state.scopes.declare(name);
+                    Statement::Set{ var, value }
+                }
+            }
+            // `$` assigns to the nearest existing declaration instead
+            4 => {
+                let Lexeme { token, span } = tokens[*index].clone();
+                let name = token.name_id().ok_or(ParseError::ExpectedName { found: token, span })?;
+                let var = state.scopes.resolve(name).ok_or(ParseError::UndefinedName { span })?;
                 *index += 1;
-                let value: Box<Statement> = Box::from(parse_expression(tokens, index,1,listener));
-                Statement::Set{ name, value }
+                if is_adjacent_bracket(tokens, *index, 2) {
+                    parse_set_index(tokens, index, var, state, listener)?
+                } else {
+                    let value: Box<Statement> = Box::from(parse_expression(tokens, index, 1, state, listener)?);
+                    Statement::Set{ var, value }
+                }
             }
+            // `-> |+` restarts and `-> |-` leaves the innermost block, or the
+            // enclosing one with the label that follows; the frame of a
+            // `match` does not count
             16 => {
-                let repeat = match tokens[*index] {
+                let Lexeme { token, span } = tokens[*index].clone();
+                let repeat = match token {
                     Token::Mark(17) => true,
                     Token::Mark(18) => false,
-                    _ => panic!("expected again or stop"),
+                    _ => return Err(ParseError::ExpectedJumpDirection { found: token, span }),
                 };
                 *index += 1;
-                Statement::Jump(repeat)
+                let Lexeme { token, span } = tokens[*index].clone();
+                let depth = match token {
+                    Token::Name(label) => {
+                        *index += 1;
+                        state.scopes.label_depth(label).ok_or(ParseError::UndefinedLabel { span })?
+                    }
+                    _ => state.scopes.jump_depth(),
+                };
+                Statement::Jump { repeat, depth }
             }
             19 => Statement::In(RefCell::new(FlowStreamer::None)),
             20 => {
-                let to_out: Statement = parse_statement(tokens,index,  listener);
+                let to_out: Statement = parse_statement(tokens, index, true, state, listener)?;
                 Statement::Out {
                     expr: Box::from(to_out),
                     to: listener.clone(),
@@ -55,108 +194,382 @@ fn parse_statement(tokens: &Vec<Token>, index: &mut usize, listener: &RefCell<Fl
             }
             _ => {
                 *index -= 1;
-                parse_expression(tokens, index, 1, listener)
+                state.statement = !as_value;
+                parse_expression(tokens, index, 1, state, listener)?
+            }
+        },
+        // `label: { ... }`, `label: while ...` or `label: for ...`
+        Token::Name(label) if tokens[*index].token == Token::Mark(5) => match tokens[*index + 1].token {
+            Token::Brackets { id: 3, is_opened: true } => {
+                *index += 2;
+                parse_block(tokens, index, Token::Brackets { id: 3, is_opened: false }, Some(label), state, listener)?
+            }
+            Token::Mark(25) => {
+                *index += 2;
+                parse_while(tokens, index, Some(label), state, listener)?
+            }
+            Token::Mark(26) => {
+                *index += 2;
+                parse_for(tokens, index, Some(label), state, listener)?
+            }
+            _ => {
+                *index -= 1;
+                state.statement = !as_value;
+                parse_expression(tokens, index, 1, state, listener)?
             }
         },
         _ => {
             *index -= 1;
-            parse_expression(tokens, index,1, listener)
+            state.statement = !as_value;
+            parse_expression(tokens, index, 1, state, listener)?
         }
     };
-    if tokens.len() > *index {
-        if let Token::Dot(true) = tokens[*index] {
-            *index += 1;
+    let span = start.to(tokens[index.saturating_sub(1)].span);
+    if let Token::Dot(_) = tokens[*index].token {
+        *index += 1;
+    }
+    Ok(Statement::Spanned(span, Box::from(result)))
+}
+/// `name[i][j] value`, after the name: assigns to an element of the list
+/// variable `var`.
+fn parse_set_index(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    var: Var,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, ParseError> {
+    let closing_brace = Token::Brackets { id: 2, is_opened: false };
+    let mut indices: Vec<Statement> = Vec::new();
+    while is_adjacent_bracket(tokens, *index, 2) {
+        *index += 1;
+        indices.push(parse_expression(tokens, index, 1, state, listener)?);
+        let Lexeme { token, span } = tokens[*index].clone();
+        if token != closing_brace {
+            return Err(ParseError::UnclosedBracket { expected: closing_brace, found: token, span });
         }
+        *index += 1;
     }
-    result
+    let value = parse_expression(tokens, index, 1, state, listener)?;
+    Ok(Statement::SetIndex { var, indices, value: Box::from(value) })
+}
+/// Whether an opening bracket of kind `id` at `index` directly follows the
+/// previous token, as in `f(x)` or `list[0]`.
+fn is_adjacent_bracket(tokens: &[Lexeme], index: usize, id: u8) -> bool {
+    tokens[index].token == (Token::Brackets { id, is_opened: true })
+        && index > 0
+        && tokens[index].span.start == tokens[index - 1].span.end
 }
 fn parse_if_statement(
-    tokens: &Vec<Token>,
+    tokens: &[Lexeme],
     index: &mut usize,
+    state: &mut ParseState,
     listener: &RefCell<FlowListener>,
-) -> Statement {
-    let condition = parse_statement(tokens, index, listener);
+) -> Result<Statement, ParseError> {
+    let condition = parse_statement(tokens, index, true, state, listener)?;
 
-    let if_block = parse_statement(tokens, index, listener);
-    if let Token::Mark(12) = tokens[*index] {
+    let if_block = parse_statement(tokens, index, false, state, listener)?;
+    if let Token::Mark(12) = tokens[*index].token {
         *index += 1;
-        let else_block = parse_statement(tokens, index,listener);
-        Statement::If(Box::from(condition), Box::from(if_block), Some(Box::from(else_block)))
+        let else_block = parse_statement(tokens, index, false, state, listener)?;
+        Ok(Statement::If(Box::from(condition), Box::from(if_block), Some(Box::from(else_block))))
     } else {
-        Statement::If(Box::from(condition), Box::from(if_block), None)
+        Ok(Statement::If(Box::from(condition), Box::from(if_block), None))
+    }
+}
+/// `while condition { body }`. The body's scope is the loop itself, so
+/// `-> |+` continues and `-> |-` breaks it.
+fn parse_while(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    label: Option<usize>,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, ParseError> {
+    // the condition is checked inside the loop's frame
+    state.scopes.open(label);
+    let result = parse_expression(tokens, index, 1, state, listener)
+        .and_then(|condition| Ok((condition, parse_loop_body(tokens, index, state, listener)?)));
+    state.scopes.close();
+    let (condition, body) = result?;
+    Ok(Statement::While { condition: Box::from(condition), body })
+}
+/// `for name in iterable { body }`, where the iterable is a range or a list.
+fn parse_for(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    label: Option<usize>,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, ParseError> {
+    let Lexeme { token, span } = tokens[*index].clone();
+    let name = token.name_id().ok_or(ParseError::ExpectedName { found: token, span })?;
+    *index += 1;
+    let Lexeme { token, span } = tokens[*index].clone();
+    if token != Token::Mark(19) {
+        return Err(ParseError::ExpectedIn { found: token, span });
+    }
+    *index += 1;
+    // the iterable and the loop state live in a frame around the loop's
+    state.scopes.open(None);
+    let (source, counter) = (state.scopes.reserve(), state.scopes.reserve());
+    let result = parse_expression(tokens, index, 1, state, listener).and_then(|iterable| {
+        state.scopes.open(label);
+        let var = state.scopes.declare(name);
+        let body = parse_loop_body(tokens, index, state, listener);
+        state.scopes.close();
+        Ok((iterable, var, body?))
+    });
+    state.scopes.close();
+    let (iterable, var, body) = result?;
+    Ok(Statement::For { var, iterable: Box::from(iterable), source, counter, body })
+}
+/// `{ body }` of a loop, parsed into the loop's scope.
+fn parse_loop_body(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Vec<Statement>, ParseError> {
+    let Lexeme { token, span } = tokens[*index].clone();
+    if token != (Token::Brackets { id: 3, is_opened: true }) {
+        return Err(ParseError::ExpectedLoopBody { found: token, span });
+    }
+    *index += 1;
+    parse_statements(tokens, index, Token::Brackets { id: 3, is_opened: false }, state, listener)
+}
+/// `=> name (param, ...) body`
+fn parse_function(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, ParseError> {
+    let Lexeme { token, span } = tokens[*index].clone();
+    let name = token.name_id().ok_or(ParseError::ExpectedName { found: token, span })?;
+    *index += 1;
+    let Lexeme { token, span } = tokens[*index].clone();
+    if token != (Token::Brackets { id: 1, is_opened: true }) {
+        return Err(ParseError::ExpectedParameters { found: token, span });
+    }
+    *index += 1;
+    let mut params: Vec<usize> = Vec::new();
+    loop {
+        let Lexeme { token, span } = tokens[*index].clone();
+        match token {
+            Token::Brackets { id: 1, is_opened: false } => {
+                *index += 1;
+                break;
+            }
+            Token::Name(param) => {
+                params.push(param);
+                *index += 1;
+                if let Token::Dot(true) = tokens[*index].token {
+                    *index += 1;
+                }
+            }
+            _ => return Err(ParseError::ExpectedName { found: token, span }),
+        }
+    }
+    // the body sees its parameters only, never the caller's variables
+    let outer = mem::replace(&mut state.scopes, Scopes::with_params(&params));
+    let body = parse_statement(tokens, index, false, state, listener);
+    state.scopes = outer;
+    let body = body?;
+    Ok(Statement::Function { name, params, body: Box::from(body) })
+}
+/// Comma separated expressions after an opening bracket, such as call
+/// arguments or list elements; a trailing comma is allowed.
+fn parse_arguments(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    closing_brace: Token,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Vec<Statement>, ParseError> {
+    let mut args: Vec<Statement> = Vec::new();
+    loop {
+        if tokens[*index].token == closing_brace {
+            *index += 1;
+            return Ok(args);
+        }
+        args.push(parse_expression(tokens, index, 1, state, listener)?);
+        let Lexeme { token, span } = tokens[*index].clone();
+        match token {
+            Token::Dot(true) => *index += 1,
+            _ if token == closing_brace => (),
+            _ => return Err(ParseError::UnclosedBracket { expected: closing_brace, found: token, span }),
+        }
     }
 }
 fn parse_expression(
-    tokens: &Vec<Token>,
+    tokens: &[Lexeme],
     index: &mut usize,
     min_priority: u8,
+    state: &mut ParseState,
     listener: &RefCell<FlowListener>,
-) -> Statement {
-    if tokens.len() <= *index {
-        return Statement::Nil;
-    }
-    let mut left_expr: Statement = parse_primary(tokens, index, listener);
+) -> Result<Statement, ParseError> {
+    let statement = mem::take(&mut state.statement);
+    let mut left_expr: Statement = parse_primary(tokens, index, state, listener)?;
     loop {
-        let op: Token = tokens[*index];
+        let Lexeme { token: op, span } = tokens[*index].clone();
         let priority = op.get_operation_priorety();
         if priority < min_priority || !op.is_operation() {
             break;
         }
+        pattern::check_exhaustive(&left_expr, true, state);
         *index += 1;
-        let right_expr: Statement = parse_expression(tokens, index,priority + 1, listener);
+        let right_expr: Statement = parse_expression(tokens, index, priority + 1, state, listener)?;
         left_expr = match &op {
             Token::Sign(_) => Statement::OperationNumder(
                 op.token_to_action_type(),
                 Box::from(left_expr),
                 Box::from(right_expr),
-            )
-            .into(),
+            ),
             Token::Comparsion(_) => Statement::Comparsion(
                 op.token_to_comparsion_type(),
                 Box::from(left_expr),
                 Box::from(right_expr),
-            )
-            .into(),
+            ),
             Token::Mark(1 | 7 | 9) => Statement::OperationBool(
                 op.token_to_action_type(),
                 Box::from(left_expr),
                 Some(Box::from(right_expr)),
             ),
-            _ => panic!("Invalid operation to operate"),
+            Token::Mark(24) => Statement::Range(Box::from(left_expr), Box::from(right_expr)),
+            _ => return Err(ParseError::UnexpectedToken { found: op, span }),
         };
     }
-    left_expr
+    pattern::check_exhaustive(&left_expr, !statement, state);
+    Ok(left_expr)
 }
-fn parse_primary<'a>(tokens: &Vec<Token>,index: &mut usize, listener: &RefCell<FlowListener>) -> Statement {
-    if tokens.len() <= *index {
-        return Statement::Nil;
+fn parse_primary(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, ParseError> {
+    let Lexeme { token: tk, span } = tokens[*index].clone();
+    if tk == Token::EOF {
+        return Err(ParseError::UnexpectedToken { found: tk, span });
     }
-    let tk: Token = tokens[*index];
     *index += 1;
-    match tk {
-        Token::Brackets { id, is_opened } => parse_brackets(tokens, index, id, is_opened, listener),
+    let expr = match tk {
+        Token::Brackets { id, is_opened } => parse_brackets(tokens, index, id, is_opened, span, state, listener)?,
         Token::Mark(1) | Token::Sign(2) => {
-            let expr: Statement = parse_expression(tokens, index, 5, listener);
-            Statement::OperationBool(ActionV::Not, Box::from(expr), None)
+            let expr: Statement = parse_expression(tokens, index, 5, state, listener)?;
+            return Ok(Statement::OperationBool(ActionV::Not, Box::from(expr), None));
+        }
+        Token::Mark(23) => pattern::parse_match(tokens, index, span, state, listener)?,
+        // binds tighter than any binary operator: `len list - 1`
+        Token::Mark(2) => {
+            let expr: Statement = parse_expression(tokens, index, 7, state, listener)?;
+            return Ok(Statement::Length(Box::from(expr)));
         }
         Token::Number(val) => Statement::Number(val),
+        Token::Float(val) => Statement::Float(val),
+        #[cfg(feature = "bigint")]
+        Token::BigNumber(val) => Statement::BigNumber(val),
+        Token::Str(val) => Statement::Str(val),
         Token::Bool(val) => Statement::Bool(val),
-        Token::Name(name) => Statement::Name(name),
-        v => {println!("unexpected token in primary expression: {:?}", v); Statement::Nil},
+        // a parenthesis right after a name, without whitespace, is a call
+        Token::Name(name) if is_adjacent_bracket(tokens, *index, 1) => {
+            *index += 1;
+            let args = parse_arguments(tokens, index, Token::Brackets { id: 1, is_opened: false }, state, listener)?;
+            Statement::Call { name, args }
+        }
+        Token::Name(name) => state.scopes.resolve(name).map(Statement::Name).ok_or(ParseError::UndefinedName { span })?,
+        v => return Err(ParseError::UnexpectedToken { found: v, span }),
+    };
+    parse_subscripts(tokens, index, expr, state, listener)
+}
+/// Index and slice suffixes directly following a primary: `list[i]`,
+/// `list[start:end]`, `list[:end]`, `list[i][j]`.
+fn parse_subscripts(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    mut expr: Statement,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, ParseError> {
+    let closing_brace = Token::Brackets { id: 2, is_opened: false };
+    while is_adjacent_bracket(tokens, *index, 2) {
+        pattern::check_exhaustive(&expr, true, state);
+        *index += 1;
+        let start = match tokens[*index].token {
+            Token::Mark(5) => None,
+            _ => Some(Box::from(parse_expression(tokens, index, 1, state, listener)?)),
+        };
+        let list = Box::from(expr);
+        expr = match (tokens[*index].token.clone(), start) {
+            (Token::Mark(5), start) => {
+                *index += 1;
+                let end = if tokens[*index].token == closing_brace {
+                    None
+                } else {
+                    Some(Box::from(parse_expression(tokens, index, 1, state, listener)?))
+                };
+                Statement::Slice { list, start, end }
+            }
+            (_, Some(start)) => Statement::Index { list, index: start },
+            (found, None) => return Err(ParseError::UnexpectedToken { found, span: tokens[*index].span }),
+        };
+        let Lexeme { token, span } = tokens[*index].clone();
+        if token != closing_brace {
+            return Err(ParseError::UnclosedBracket { expected: closing_brace, found: token, span });
+        }
+        *index += 1;
     }
+    Ok(expr)
 }
 fn parse_brackets(
-    tokens: &Vec<Token>,
+    tokens: &[Lexeme],
     index: &mut usize,
     id: u8,
     is_opened: bool,
+    span: Span,
+    state: &mut ParseState,
     listener: &RefCell<FlowListener>,
-) -> Statement {
+) -> Result<Statement, ParseError> {
     if is_opened {
         let closing_brace = Token::Brackets { id, is_opened: false };
-        let block = parse_block(tokens, index, closing_brace, listener);
-        block
+        if id == 2 {
+            return parse_arguments(tokens, index, closing_brace, state, listener).map(Statement::List);
+        }
+        parse_block(tokens, index, closing_brace, None, state, listener)
     } else {
-        panic!("unexpected closing bracket");
+        Err(ParseError::UnexpectedClosingBracket { found: Token::Brackets { id, is_opened }, span })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inout::{read_json, Vocabulary};
+    use crate::lexer::tokenize_code;
+
+    /// Every error parsing `text` reports.
+    fn errors(text: &str) -> Vec<ParseError> {
+        let vocabulary: Vocabulary = read_json("vocabulary.json".to_string());
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        let tokens = tokenize_code(text.to_string(), &vocabulary, None, Some(&mut Vec::new()), &mut diagnostics);
+        assert!(diagnostics.is_empty(), "test program does not lex");
+        let listener = RefCell::new(FlowListener::Capture(Default::default()));
+        parse_program(&tokens, &mut diagnostics, &listener).expect_err("test program parses")
+    }
+    #[test]
+    fn reports_every_error() {
+        let errors = errors("# x ),\n~- 1,\n# y ),\n~- 2");
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|error| matches!(error, ParseError::UnexpectedClosingBracket { .. })));
+        let spans: Vec<Span> = errors.iter().map(ParseError::span).collect();
+        let closing = |start, line| Span { start, end: start + 1, line, column: 5 };
+        assert_eq!(spans, [closing(4, 1), closing(17, 3)]);
+    }
+    #[test]
+    fn parses_on_after_a_bad_statement() {
+        // `y` is declared after the error, so only `z` is undefined
+        let errors = errors("# x ),\n# y 1,\n~- y + z");
+        assert!(matches!(errors[..], [ParseError::UnexpectedClosingBracket { .. }, ParseError::UndefinedName { .. }]));
+        assert_eq!(errors[1].span(), Span { start: 21, end: 22, line: 3, column: 8 });
     }
 }
@@ -0,0 +1,110 @@
+use std::fmt;
+
+use crate::diagnostic::{Diagnostic, Span};
+use crate::lexer::Token;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnclosedBracket { expected: Token, found: Token, span: Span },
+    UnexpectedClosingBracket { found: Token, span: Span },
+    ExpectedName { found: Token, span: Span },
+    ExpectedParameters { found: Token, span: Span },
+    ExpectedJumpDirection { found: Token, span: Span },
+    UnexpectedToken { found: Token, span: Span },
+    UndefinedName { span: Span },
+    /// `# name[i] value` where `name` is declared in an enclosing block.
+    NotLocal { span: Span },
+    ExpectedArms { found: Token, span: Span },
+    ExpectedPattern { found: Token, span: Span },
+    PatternCount { expected: usize, found: usize, span: Span },
+    ExpectedArmBody { found: Token, span: Span },
+    /// A match used as a value that may run none of its arms.
+    InexhaustiveValue { span: Span },
+    UndefinedLabel { span: Span },
+    ExpectedIn { found: Token, span: Span },
+    ExpectedLoopBody { found: Token, span: Span },
+}
+impl ParseError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParseError::UnclosedBracket { span, .. }
+            | ParseError::UnexpectedClosingBracket { span, .. }
+            | ParseError::ExpectedName { span, .. }
+            | ParseError::ExpectedParameters { span, .. }
+            | ParseError::ExpectedJumpDirection { span, .. }
+            | ParseError::UnexpectedToken { span, .. }
+            | ParseError::UndefinedName { span }
+            | ParseError::NotLocal { span }
+            | ParseError::UndefinedLabel { span }
+            | ParseError::ExpectedIn { span, .. }
+            | ParseError::ExpectedLoopBody { span, .. }
+            | ParseError::ExpectedArms { span, .. }
+            | ParseError::ExpectedPattern { span, .. }
+            | ParseError::PatternCount { span, .. }
+            | ParseError::ExpectedArmBody { span, .. }
+            | ParseError::InexhaustiveValue { span } => *span,
+        }
+    }
+}
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnclosedBracket { expected, found, .. } => {
+                write!(f, "expected closing brace: {:?}, found: {:?}", expected, found)
+            }
+            ParseError::UnexpectedClosingBracket { found, .. } => {
+                write!(f, "unexpected closing bracket: {:?}", found)
+            }
+            ParseError::ExpectedName { found, .. } => {
+                write!(f, "expected name token, found: {:?}", found)
+            }
+            ParseError::ExpectedParameters { found, .. } => {
+                write!(f, "expected parameter list in parentheses, found: {:?}", found)
+            }
+            ParseError::ExpectedJumpDirection { found, .. } => {
+                write!(f, "expected again or stop, found: {:?}", found)
+            }
+            ParseError::UnexpectedToken { found: Token::EOF, .. } => {
+                write!(f, "unexpected end of file")
+            }
+            ParseError::UnexpectedToken { found, .. } => {
+                write!(f, "unexpected token in primary expression: {:?}", found)
+            }
+            ParseError::UndefinedName { .. } => {
+                write!(f, "variable is not declared in this scope")
+            }
+            ParseError::NotLocal { .. } => {
+                write!(f, "variable is declared in an enclosing block, assign its elements with `$`")
+            }
+            ParseError::UndefinedLabel { .. } => {
+                write!(f, "no enclosing block has this label")
+            }
+            ParseError::ExpectedIn { found, .. } => {
+                write!(f, "expected in after the loop variable, found: {:?}", found)
+            }
+            ParseError::ExpectedLoopBody { found, .. } => {
+                write!(f, "expected loop body in braces, found: {:?}", found)
+            }
+            ParseError::ExpectedArms { found, .. } => {
+                write!(f, "expected match arms in braces, found: {:?}", found)
+            }
+            ParseError::ExpectedPattern { found, .. } => {
+                write!(f, "expected pattern, found: {:?}", found)
+            }
+            ParseError::PatternCount { expected, found, .. } => {
+                write!(f, "match subject has {} values but the arm has {} patterns", expected, found)
+            }
+            ParseError::ExpectedArmBody { found, .. } => {
+                write!(f, "expected `:` before the arm body, found: {:?}", found)
+            }
+            ParseError::InexhaustiveValue { .. } => {
+                write!(f, "match is used as a value but is not exhaustive: add a `_` arm")
+            }
+        }
+    }
+}
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        Diagnostic::error(error.to_string(), error.span())
+    }
+}
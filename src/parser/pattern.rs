@@ -0,0 +1,190 @@
+use std::cell::RefCell;
+
+use super::{parse_arguments, parse_expression, parse_statement, ParseError, ParseState};
+use crate::diagnostic::{Diagnostic, Span};
+use crate::lexer::{Lexeme, Token};
+use crate::types::{FlowListener, MatchArm, Pattern, Statement};
+
+/// `match subject { pattern ? guard: body, ... }`, after the keyword at
+/// `span`. Several values in parentheses as the subject are matched by as
+/// many comma separated patterns in parentheses; a single value that is a
+/// tuple, such as a function's results, by a tuple pattern.
+pub fn parse_match(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    span: Span,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, ParseError> {
+    // the whole match runs in a frame of its own, holding the subject and
+    // the bindings of every arm; jumps in the arms go past it
+    state.scopes.open_hidden();
+    let result = parse_match_body(tokens, index, state, listener);
+    state.scopes.close();
+    let span = span.to(tokens[*index - 1].span);
+    Ok(Statement::Spanned(span, Box::from(result?)))
+}
+/// Reports `statement` if it is a match that may run none of its arms: a
+/// warning where it is a statement of its own, and an error where it is
+/// used as a value, since it would leave none.
+pub fn check_exhaustive(statement: &Statement, as_value: bool, state: &mut ParseState) {
+    let Statement::Spanned(span, statement) = statement else {
+        return;
+    };
+    let Statement::Match { arms, .. } = statement.as_ref() else {
+        return check_exhaustive(statement, as_value, state);
+    };
+    if is_exhaustive(arms) {
+        return;
+    }
+    if as_value {
+        state.errors.push(ParseError::InexhaustiveValue { span: *span });
+    } else {
+        state.warnings.push(Diagnostic::warning(
+            "match is not exhaustive: values no arm matches are skipped, add a `_` arm",
+            *span,
+        ));
+    }
+}
+fn parse_match_body(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<Statement, ParseError> {
+    let subject = if tokens[*index].token == (Token::Brackets { id: 1, is_opened: true }) {
+        *index += 1;
+        parse_arguments(tokens, index, Token::Brackets { id: 1, is_opened: false }, state, listener)?
+    } else {
+        vec![parse_expression(tokens, index, 1, state, listener)?]
+    };
+    let temps = subject.iter().map(|_| state.scopes.reserve()).collect::<Vec<_>>();
+    let Lexeme { token, span } = tokens[*index].clone();
+    if token != (Token::Brackets { id: 3, is_opened: true }) {
+        return Err(ParseError::ExpectedArms { found: token, span });
+    }
+    *index += 1;
+    let closing_brace = Token::Brackets { id: 3, is_opened: false };
+    let mut arms: Vec<MatchArm> = Vec::new();
+    loop {
+        let Lexeme { token, span } = tokens[*index].clone();
+        if token == closing_brace {
+            *index += 1;
+            break;
+        }
+        if let Token::EOF | Token::Brackets { is_opened: false, .. } = token {
+            return Err(ParseError::UnclosedBracket { expected: closing_brace, found: token, span });
+        }
+        // bindings of one arm are invisible to the next
+        let mark = state.scopes.mark();
+        let arm = parse_arm(tokens, index, temps.len(), state, listener);
+        state.scopes.forget(mark);
+        arms.push(arm?);
+    }
+    Ok(Statement::Match { subject, temps, arms })
+}
+fn parse_arm(
+    tokens: &[Lexeme],
+    index: &mut usize,
+    arity: usize,
+    state: &mut ParseState,
+    listener: &RefCell<FlowListener>,
+) -> Result<MatchArm, ParseError> {
+    let start = tokens[*index].span;
+    let patterns = if arity == 1 {
+        vec![parse_pattern(tokens, index, state)?]
+    } else {
+        parse_tuple_pattern(tokens, index, state)?
+    };
+    if patterns.len() != arity {
+        let span = start.to(tokens[*index - 1].span);
+        return Err(ParseError::PatternCount { expected: arity, found: patterns.len(), span });
+    }
+    let guard = match tokens[*index].token {
+        Token::Mark(8) => {
+            *index += 1;
+            Some(parse_expression(tokens, index, 1, state, listener)?)
+        }
+        _ => None,
+    };
+    let Lexeme { token, span } = tokens[*index].clone();
+    if token != Token::Mark(5) {
+        return Err(ParseError::ExpectedArmBody { found: token, span });
+    }
+    *index += 1;
+    let body = parse_statement(tokens, index, false, state, listener)?;
+    Ok(MatchArm { patterns, guard, body })
+}
+/// `(pattern, ...)`
+fn parse_tuple_pattern(tokens: &[Lexeme], index: &mut usize, state: &mut ParseState) -> Result<Vec<Pattern>, ParseError> {
+    let Lexeme { token, span } = tokens[*index].clone();
+    if token != (Token::Brackets { id: 1, is_opened: true }) {
+        return Err(ParseError::ExpectedPattern { found: token, span });
+    }
+    *index += 1;
+    let closing_brace = Token::Brackets { id: 1, is_opened: false };
+    let mut patterns: Vec<Pattern> = Vec::new();
+    loop {
+        if tokens[*index].token == closing_brace {
+            *index += 1;
+            return Ok(patterns);
+        }
+        patterns.push(parse_pattern(tokens, index, state)?);
+        let Lexeme { token, span } = tokens[*index].clone();
+        match token {
+            Token::Dot(true) => *index += 1,
+            _ if token == closing_brace => (),
+            _ => return Err(ParseError::UnclosedBracket { expected: closing_brace, found: token, span }),
+        }
+    }
+}
+/// `_`, a name to bind, a literal, optionally negated, or a tuple of
+/// patterns in parentheses.
+fn parse_pattern(tokens: &[Lexeme], index: &mut usize, state: &mut ParseState) -> Result<Pattern, ParseError> {
+    let Lexeme { token, span } = tokens[*index].clone();
+    *index += 1;
+    let pattern = match token {
+        // `(pattern)` is just the pattern, a tuple has at least two
+        Token::Brackets { id: 1, is_opened: true } => {
+            *index -= 1;
+            let mut patterns = parse_tuple_pattern(tokens, index, state)?;
+            match patterns.len() {
+                1 => patterns.remove(0),
+                _ => Pattern::Tuple(patterns),
+            }
+        }
+        Token::Mark(10) => Pattern::Wildcard,
+        Token::Name(name) => Pattern::Bind(state.scopes.declare(name)),
+        Token::Number(val) => Pattern::Value(Statement::Number(val)),
+        Token::Float(val) => Pattern::Value(Statement::Float(val)),
+        #[cfg(feature = "bigint")]
+        Token::BigNumber(val) => Pattern::Value(Statement::BigNumber(val)),
+        Token::Str(val) => Pattern::Value(Statement::Str(val)),
+        Token::Bool(val) => Pattern::Value(Statement::Bool(val)),
+        Token::Sign(2) => match tokens[*index].token.clone() {
+            Token::Number(val) => {
+                *index += 1;
+                Pattern::Value(Statement::Number(-val))
+            }
+            Token::Float(val) => {
+                *index += 1;
+                Pattern::Value(Statement::Float(-val))
+            }
+            found => return Err(ParseError::ExpectedPattern { found, span: tokens[*index].span }),
+        },
+        found => {
+            *index -= 1;
+            return Err(ParseError::ExpectedPattern { found, span });
+        }
+    };
+    Ok(pattern)
+}
+/// Whether some arm matches every value: one without a guard whose patterns
+/// are all irrefutable, or unguarded `==` and `!!` arms for a single value.
+fn is_exhaustive(arms: &[MatchArm]) -> bool {
+    let unguarded = || arms.iter().filter(|arm| arm.guard.is_none());
+    let covers = |val: bool| {
+        unguarded().any(|arm| arm.patterns == [Pattern::Value(Statement::Bool(val))])
+    };
+    unguarded().any(|arm| arm.patterns.iter().all(Pattern::is_irrefutable)) || (covers(true) && covers(false))
+}
@@ -0,0 +1,89 @@
+use crate::types::Var;
+
+/// Names declared in each lexically enclosing block, innermost last. A name's
+/// slot is its position in the block that declared it; slots of hidden
+/// temporaries and forgotten names stay taken so the frame layout is stable.
+/// Blocks may also carry a label for jumps out of nested blocks, or be
+/// hidden from jumps altogether.
+#[derive(Debug, Clone, Default)]
+pub struct Scopes {
+    blocks: Vec<Vec<Option<usize>>>,
+    labels: Vec<Option<usize>>,
+    /// Blocks a jump without a label passes through, like the frame of a
+    /// `match`, which is not a block in the source.
+    hidden: Vec<bool>,
+}
+impl Scopes {
+    /// Scopes of a function body, which only sees its parameters.
+    pub fn with_params(params: &[usize]) -> Self {
+        Scopes { blocks: vec![params.iter().copied().map(Some).collect()], labels: vec![None], hidden: vec![false] }
+    }
+    pub fn open(&mut self, label: Option<usize>) {
+        self.blocks.push(Vec::new());
+        self.labels.push(label);
+        self.hidden.push(false);
+    }
+    /// Opens a scope that `jump_depth` skips.
+    pub fn open_hidden(&mut self) {
+        self.open(None);
+        *self.hidden.last_mut().expect("no open scope") = true;
+    }
+    pub fn close(&mut self) {
+        self.blocks.pop();
+        self.labels.pop();
+        self.hidden.pop();
+    }
+    fn innermost(&mut self) -> &mut Vec<Option<usize>> {
+        self.blocks.last_mut().expect("no open scope")
+    }
+    /// Declares `name` in the innermost scope, or returns the slot it already
+    /// has there.
+    pub fn declare(&mut self, name: usize) -> Var {
+        let block = self.innermost();
+        let slot = match block.iter().position(|declared| *declared == Some(name)) {
+            Some(slot) => slot,
+            None => {
+                block.push(Some(name));
+                block.len() - 1
+            }
+        };
+        Var { name, depth: 0, slot }
+    }
+    /// A slot in the innermost scope that no name resolves to.
+    pub fn reserve(&mut self) -> Var {
+        let block = self.innermost();
+        block.push(None);
+        Var { name: usize::MAX, depth: 0, slot: block.len() - 1 }
+    }
+    /// Number of slots taken in the innermost scope, to pass to `forget`.
+    pub fn mark(&mut self) -> usize {
+        self.innermost().len()
+    }
+    /// Hides the names declared in the innermost scope since `mark`.
+    pub fn forget(&mut self, mark: usize) {
+        for declared in &mut self.innermost()[mark..] {
+            *declared = None;
+        }
+    }
+    /// How many blocks out the innermost one that is not hidden is, which
+    /// a jump without a label restarts or leaves.
+    pub fn jump_depth(&self) -> usize {
+        self.hidden.iter().rev().position(|hidden| !hidden).unwrap_or(0)
+    }
+    /// How many blocks out the nearest one labeled `label` is.
+    pub fn label_depth(&self, label: usize) -> Option<usize> {
+        self.labels.iter().rev().position(|block| *block == Some(label))
+    }
+    /// The declaration of `name` in the innermost scope, if it has one.
+    pub fn local(&self, name: usize) -> Option<Var> {
+        let slot = self.blocks.last()?.iter().position(|declared| *declared == Some(name))?;
+        Some(Var { name, depth: 0, slot })
+    }
+    /// The nearest declaration of `name`, looking outwards.
+    pub fn resolve(&self, name: usize) -> Option<Var> {
+        self.blocks.iter().rev().enumerate().find_map(|(depth, block)| {
+            let slot = block.iter().position(|declared| *declared == Some(name))?;
+            Some(Var { name, depth, slot })
+        })
+    }
+}
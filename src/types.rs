@@ -1,21 +1,90 @@
 use std::cell::RefCell;
+use std::rc::Rc;
 
+use crate::diagnostic::Span;
 use crate::vm::VarV;
+/// A variable resolved by the parser: `depth` counts the blocks between the
+/// use and the declaring block, `slot` is its index inside that block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Var {
+    pub name: usize,
+    pub depth: usize,
+    pub slot: usize,
+}
 #[derive(Debug, Clone, PartialEq)]
 pub enum Statement {
-    Block(Vec<Box<Statement>>),
-    Set { name: usize, value: Box<Statement> },
+    Block(Vec<Statement>),
+    Set { var: Var, value: Box<Statement> },
     Nil,
-    Name(usize),
+    Name(Var),
     Bool(bool),
     Number(isize),
+    Float(f64),
+    #[cfg(feature = "bigint")]
+    BigNumber(num_bigint::BigInt),
+    Str(String),
     Comparsion(ComparsionV, Box<Statement>, Box<Statement>),
     OperationBool(ActionV, Box<Statement>, Option<Box<Statement>>),
     OperationNumder(ActionV, Box<Statement>, Box<Statement>),
     If(Box<Statement>, Box<Statement>, Option<Box<Statement>>),
     Out { expr: Box<Statement>, to: RefCell<FlowListener> },
     In(RefCell<FlowStreamer>),
-    Jump(bool),
+    /// Restarts (`repeat`) or leaves the block `depth` levels out.
+    Jump { repeat: bool, depth: usize },
+    Function { name: usize, params: Vec<usize>, body: Box<Statement> },
+    Call { name: usize, args: Vec<Statement> },
+    Return(Option<Box<Statement>>),
+    List(Vec<Statement>),
+    Index { list: Box<Statement>, index: Box<Statement> },
+    /// `list[start:end]`, either bound may be left out.
+    Slice { list: Box<Statement>, start: Option<Box<Statement>>, end: Option<Box<Statement>> },
+    Length(Box<Statement>),
+    /// `# list[i][j] value`: replaces one element of a (nested) list variable.
+    SetIndex { var: Var, indices: Vec<Statement>, value: Box<Statement> },
+    /// `match subject { pattern ? guard: body, ... }`; the subject values are
+    /// kept in `temps` while the arms are tried in order.
+    Match { subject: Vec<Statement>, temps: Vec<Var>, arms: Vec<MatchArm> },
+    /// `start .. end`, the integers from `start` up to but excluding `end`.
+    Range(Box<Statement>, Box<Statement>),
+    /// `while condition { body }`; the condition is checked in the body's
+    /// scope before every pass.
+    While { condition: Box<Statement>, body: Vec<Statement> },
+    /// `for var in iterable { body }` over a range or a list. `source` keeps
+    /// the list or the range end and `counter` the position, both in a scope
+    /// around the body's.
+    For { var: Var, iterable: Box<Statement>, source: Var, counter: Var, body: Vec<Statement> },
+    Spanned(Span, Box<Statement>),
+}
+/// One arm of a `Statement::Match`, with a pattern per subject value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub patterns: Vec<Pattern>,
+    pub guard: Option<Statement>,
+    pub body: Statement,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`
+    Wildcard,
+    Bind(Var),
+    /// A literal the value must be equal to.
+    Value(Statement),
+    /// `(pattern, ...)`, a tuple with as many elements, each matching its
+    /// pattern.
+    Tuple(Vec<Pattern>),
+}
+impl Pattern {
+    pub fn is_irrefutable(&self) -> bool {
+        matches!(self, Pattern::Wildcard | Pattern::Bind(_))
+    }
+    /// The variables the pattern binds, nested ones included.
+    pub fn bindings(&self) -> Vec<Var> {
+        match self {
+            Pattern::Bind(var) => vec![*var],
+            Pattern::Tuple(patterns) => patterns.iter().flat_map(Pattern::bindings).collect(),
+            Pattern::Wildcard | Pattern::Value(_) => Vec::new(),
+        }
+    }
 }
 #[derive(Debug, Clone, PartialEq)]
 pub enum ActionV {
@@ -40,16 +109,18 @@ pub enum ComparsionV {
 #[derive(PartialEq, Clone, Debug)]
 pub enum FlowListener{
     Console,
+    /// Collects the printed lines instead, to compare runs.
+    Capture(Rc<RefCell<Vec<String>>>),
 }
 impl FlowListener {
     pub fn get(&self,val: VarV) -> bool {
         match self {
             FlowListener::Console => {
-                match val {
-                    VarV::Num(val) => println!("{}",val),
-                    VarV::Bool(val) => println!("{}",val),
-                    VarV::Tuple(vec) => println!("{:?}",vec),   
-                }
+                println!("{}",val);
+                true
+            }
+            FlowListener::Capture(lines) => {
+                lines.borrow_mut().push(val.to_string());
                 true
             }
         }
@@ -67,9 +138,26 @@ impl FlowStreamer {
             FlowStreamer::Console => {
                 let mut input = String::new();
                 std::io::stdin().read_line(&mut input).unwrap();
-                VarV::Num(input.parse().unwrap())
+                let input = input.trim();
+                if let Ok(val) = input.parse() {
+                    VarV::Num(val)
+                } else if let Some(val) = parse_big(input) {
+                    val
+                } else if let Ok(val) = input.parse() {
+                    VarV::Float(val)
+                } else {
+                    VarV::Str(input.to_string())
+                }
             }
             FlowStreamer::None => VarV::Num(0),
         }
     }
 }
+#[cfg(feature = "bigint")]
+fn parse_big(input: &str) -> Option<VarV> {
+    input.parse().ok().map(VarV::Big)
+}
+#[cfg(not(feature = "bigint"))]
+fn parse_big(_: &str) -> Option<VarV> {
+    None
+}
@@ -1,133 +1,439 @@
 use std::collections::HashMap;
+mod error;
 mod var;
+pub use error::{ErrorKind, Frame, RuntimeError};
 use crate::ir::{MatchPattern, IR};
-use crate::types::ActionV;
-#[derive(Debug, Clone, PartialEq, Eq)]
+use crate::types::{ActionV, ComparsionV, Var};
+#[derive(Debug, Clone)]
 pub enum VarV {
     Tuple(Vec<VarV>),
+    List(Vec<VarV>),
     Num(isize),
+    /// Integer outside the `isize` range; results that fit are stored as
+    /// `Num` again.
+    #[cfg(feature = "bigint")]
+    Big(num_bigint::BigInt),
+    Float(f64),
     Bool(bool),
+    Str(String),
+}
+/// What integer arithmetic does when the result does not fit into `isize`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// Stop with `ErrorKind::Overflow`.
+    Trap,
+    Wrap,
+    Saturate,
+    /// Continue with an arbitrary-precision `VarV::Big`.
+    #[cfg(feature = "bigint")]
+    Promote,
+}
+impl Default for Overflow {
+    #[cfg(feature = "bigint")]
+    fn default() -> Self {
+        Overflow::Promote
+    }
+    #[cfg(not(feature = "bigint"))]
+    fn default() -> Self {
+        Overflow::Trap
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Options {
+    pub overflow: Overflow,
+}
+/// Deepest allowed nesting of function calls.
+pub const MAX_CALL_DEPTH: usize = 1000;
+/// Most slots a frame may have; `ir::verify` rejects code that would need
+/// more.
+pub const MAX_SLOTS: usize = 1 << 16;
+/// Most elements a range used as a value may have. `for` over a range
+/// counts without building the list, so it has no limit.
+pub const MAX_RANGE_LEN: usize = 1 << 20;
+#[derive(Clone, Copy)]
+struct Function {
+    arity: usize,
+    entry: usize,
+}
+/// Values of one block's variables by slot, `None` until assigned.
+pub type Scope = Vec<Option<VarV>>;
+/// State shared by every frame of one program run.
+pub struct Runtime {
+    pub options: Options,
+    functions: HashMap<usize, Function>,
+}
+impl Runtime {
+    pub fn new(options: Options) -> Self {
+        Runtime { options, functions: HashMap::new() }
+    }
+}
+/// A call in progress: what the caller had when it made the call.
+struct Call {
+    function: usize,
+    /// Index of the `IR::Call` instruction.
+    from: usize,
+    env: Vec<Scope>,
+    stack: Vec<VarV>,
+    blocks: Vec<Vec<VarV>>,
+    entries: Vec<usize>,
+}
+/// The value stacks of one run. Each block has a stack of its own, those
+/// of the blocks around the current one are kept in `blocks`.
+struct Machine {
+    stack: Vec<VarV>,
+    blocks: Vec<Vec<VarV>>,
+    /// Index of the `IR::Enter` of each block open in the current call,
+    /// innermost last.
+    entries: Vec<usize>,
+    calls: Vec<Call>,
+}
+enum Flow {
+    Next,
+    Goto(usize),
+    /// The program returned outside of any function.
+    Halt(VarV),
 }
 
-pub fn execute(ir: Vec<IR>, heap: &mut HashMap<usize, VarV>) -> VarV {
-    let mut stack: Vec<VarV> = Vec::new();
+/// Runs `ir` with `env` as the enclosing frames, innermost last. Frames the
+/// program opens are gone again when it finishes, even through an error.
+pub fn execute(ir: &[IR], env: &mut Vec<Scope>, runtime: &mut Runtime) -> Result<VarV, RuntimeError> {
+    let frames = env.len();
+    let mut machine = Machine { stack: Vec::new(), blocks: Vec::new(), entries: Vec::new(), calls: Vec::new() };
+    let result = run(ir, &mut machine, env, runtime);
+    if let Some(call) = machine.calls.drain(..).next() {
+        *env = call.env;
+    }
+    env.truncate(frames);
+    result
+}
+fn run(ir: &[IR], machine: &mut Machine, env: &mut Vec<Scope>, runtime: &mut Runtime) -> Result<VarV, RuntimeError> {
     let mut index = 0;
     while ir.len() > index {
-        let instruction = ir.get(index).unwrap();
-        match instruction {
-            IR::Nil => (),
-            IR::Num(n) => stack.push(VarV::Num(*n)),
-            IR::Bool(b) => stack.push(VarV::Bool(*b)),
-            IR::BinExpr(_) |
-            IR::Or |
-            IR::And |
-            IR::Not |
-            IR::Eql |
-            IR::NEql |
-            IR::Ls |
-            IR::Gt |
-            IR::LsEql |
-            IR::GtEql => {
-                do_operation(&mut stack, instruction.clone());
+        let flow = step(ir, index, machine, env, runtime).map_err(|mut error| {
+            error.trace = trace(index, machine);
+            error
+        })?;
+        match flow {
+            Flow::Next => index += 1,
+            Flow::Goto(target) if target > ir.len() => {
+                let error = RuntimeError::from(ErrorKind::InvalidJumpTarget(target));
+                return Err(RuntimeError { trace: trace(index, machine), ..error });
             }
-            IR::Store(name) => {
-                let value = stack.pop().unwrap();
-                heap.insert(*name, value);
+            Flow::Goto(target) => index = target,
+            Flow::Halt(value) => return Ok(value),
+        }
+    }
+    Ok(pack(std::mem::take(&mut machine.stack)))
+}
+/// The instruction each active call is at and the blocks open in it,
+/// innermost first.
+fn trace(index: usize, machine: &Machine) -> Vec<Frame> {
+    let calls = &machine.calls;
+    let blocks = |entries: &[usize]| entries.iter().rev().copied().collect();
+    let function = calls.last().map(|call| call.function);
+    let mut frames = vec![Frame { index, function, blocks: blocks(&machine.entries) }];
+    for (depth, call) in calls.iter().enumerate().rev() {
+        let function = depth.checked_sub(1).map(|caller| calls[caller].function);
+        frames.push(Frame { index: call.from, function, blocks: blocks(&call.entries) });
+    }
+    frames
+}
+fn step(
+    ir: &[IR],
+    index: usize,
+    machine: &mut Machine,
+    env: &mut Vec<Scope>,
+    runtime: &mut Runtime,
+) -> Result<Flow, RuntimeError> {
+    let instruction = &ir[index];
+    let stack = &mut machine.stack;
+    match instruction {
+        IR::Nil => (),
+        IR::Num(n) => stack.push(VarV::Num(*n)),
+        IR::Float(n) => stack.push(VarV::Float(*n)),
+        #[cfg(feature = "bigint")]
+        IR::BigNum(n) => stack.push(VarV::Big(n.clone())),
+        IR::Bool(b) => stack.push(VarV::Bool(*b)),
+        IR::Str(s) => stack.push(VarV::Str(s.clone())),
+        IR::BinExpr(_) |
+        IR::Or |
+        IR::And |
+        IR::Not |
+        IR::Eql |
+        IR::NEql |
+        IR::Ls |
+        IR::Gt |
+        IR::LsEql |
+        IR::GtEql => {
+            do_operation(stack, instruction, runtime.options.overflow)?;
+        }
+        IR::Store(var) => {
+            let value = pop(stack)?;
+            store(env, *var, value)?;
+        }
+        IR::Load(var) => stack.push(load(env, *var)?),
+        IR::Jump(target) => return Ok(Flow::Goto(*target)),
+        IR::Test(flag, target) => {
+            match stack.last() {
+                Some(VarV::Bool(b)) if b == flag => return Ok(Flow::Goto(*target)),
+                Some(VarV::Bool(_)) => {
+                    stack.pop();
+                }
+                Some(value) => {
+                    let action = if *flag { ActionV::Or } else { ActionV::And };
+                    return Err(ErrorKind::TypeMismatch { action, left: value.type_name(), right: None }.into());
+                }
+                None => return Err(ErrorKind::StackUnderflow.into()),
+            }
+        }
+        IR::Enter(slots) => {
+            env.push(vec![None; *slots]);
+            machine.blocks.push(std::mem::take(stack));
+            machine.entries.push(index);
+        }
+        IR::Leave => {
+            let values = leave(machine, env)?;
+            machine.stack.append(&mut unpack(pack(values)));
+        }
+        IR::Unwind(depth, target) => {
+            for _ in 0..*depth {
+                leave(machine, env)?;
             }
-            IR::Load(name) => {
-                stack.push(heap[name].clone());
+            return Ok(Flow::Goto(*target));
+        }
+        IR::Function(name, arity, entry) => {
+            runtime.functions.insert(*name, Function { arity: *arity, entry: *entry });
+        }
+        IR::Call(name, argc) => return call(*name, *argc, index, machine, env, runtime),
+        IR::Ret(has_value) => {
+            let value = if *has_value { pop(stack)? } else { VarV::Tuple(Vec::new()) };
+            return Ok(ret(value, machine, env));
+        }
+        IR::End => {
+            let value = pack(std::mem::take(stack));
+            return Ok(ret(value, machine, env));
+        }
+        IR::Case(patterns, gt) => {
+            if patterns.len() > stack.len() {
+                return Err(ErrorKind::StackUnderflow.into());
             }
-            IR::Jump(jump_index) => {
-                if *jump_index > ir.len() {
+            // all values are taken off, even when an early pattern fails
+            let values: Vec<VarV> = stack.drain(stack.len() - patterns.len()..).collect();
+            let mut is_matching = true;
+            for (pattern, value) in patterns.iter().zip(values) {
+                if !match_pattern(pattern, value, env)? {
+                    is_matching = false;
                     break;
-                } else {
-                    index = *jump_index;
-                    continue;
                 }
             }
-            IR::Efine(vec) => {
-                stack.append(&mut unpack(execute(vec.clone(), heap)));
+            if is_matching {
+                return Ok(Flow::Goto(*gt));
             }
-            IR::Case(patterns, gt) => {
-                if patterns.len() > stack.len() || gt > &ir.len() {
-                    panic!(
-                        "Pattern length is longer than stack length or goto index is out of range"
-                    );
-                }
-                let mut is_matching = true;
-                for pattern in patterns {
-                    match pattern {
-                        MatchPattern::Var(name) => {
-                            heap.insert(*name, stack.pop().unwrap());
-                        }
-                        MatchPattern::Val(val) => {
-                            if stack.pop().unwrap()
-                                != pack(unpack(execute(val.clone(), heap)))
-                            {
-                                is_matching = false;
-                                break;
-                            }
-                        }
-                        MatchPattern::Unused => {
-                            stack.pop();
-                        }
-                    }
-                }
-                if is_matching {
-                    index = *gt;
-                    continue;
-                }
+        }
+        IR::List(len) => {
+            if stack.len() < *len {
+                return Err(ErrorKind::StackUnderflow.into());
             }
-            IR::Input(streamer) => {
-                stack.push(streamer.borrow().send());
+            let items = stack.split_off(stack.len() - len);
+            stack.push(VarV::List(items));
+        }
+        IR::Index => {
+            let index = pop(stack)?;
+            let value = pop(stack)?;
+            stack.push(value.index(&index)?);
+        }
+        IR::Slice(has_start, has_end) => {
+            let end = if *has_end { Some(pop(stack)?) } else { None };
+            let start = if *has_start { Some(pop(stack)?) } else { None };
+            let value = pop(stack)?;
+            stack.push(value.slice(start.as_ref(), end.as_ref())?);
+        }
+        IR::Range => {
+            let end = pop(stack)?;
+            let start = pop(stack)?;
+            stack.push(start.range(&end)?);
+        }
+        IR::Len => {
+            let value = pop(stack)?;
+            stack.push(value.len()?);
+        }
+        IR::SetItem(depth) => {
+            let value = pop(stack)?;
+            if stack.len() < *depth + 1 {
+                return Err(ErrorKind::StackUnderflow.into());
             }
-            IR::Output(listener) => {
-                let top = stack.pop().unwrap();
-                assert!(listener.borrow().get(top));
+            let indices = stack.split_off(stack.len() - depth);
+            let list = pop(stack)?;
+            stack.push(list.set_item(&indices, value)?);
+        }
+        IR::Input(streamer) => {
+            stack.push(streamer.borrow().send());
+        }
+        IR::Output(listener) => {
+            let top = pop(stack)?;
+            assert!(listener.borrow().get(top));
+        }
+        IR::LoadBin(left, right, action) => {
+            let (left, right) = (load(env, *left)?, load(env, *right)?);
+            stack.push(left.apply(action.clone(), right, runtime.options.overflow)?);
+        }
+        IR::BinStore(action, var) => {
+            let right = pop(stack)?;
+            let left = pop(stack)?;
+            store(env, *var, left.apply(action.clone(), right, runtime.options.overflow)?)?;
+        }
+        IR::JumpUnless(comparsion, target) => {
+            let right = pop(stack)?;
+            let left = pop(stack)?;
+            if !compare(&left, &right, comparsion)? {
+                return Ok(Flow::Goto(*target));
             }
         }
-        index += 1;
     }
-    pack(stack)
+    Ok(Flow::Next)
+}
+/// The frame `var.depth` levels out from the innermost one.
+fn scope(env: &mut [Scope], var: Var) -> Result<&mut Scope, RuntimeError> {
+    let index = env.len().checked_sub(var.depth + 1).ok_or(ErrorKind::UninitializedVariable(var))?;
+    Ok(&mut env[index])
+}
+pub(crate) fn load(env: &mut [Scope], var: Var) -> Result<VarV, RuntimeError> {
+    let value = scope(env, var)?.get(var.slot).and_then(Option::as_ref);
+    Ok(value.ok_or(ErrorKind::UninitializedVariable(var))?.clone())
+}
+/// Sets a slot, growing the frame if the block declared more variables
+/// than it was entered with, as the program's own frame does.
+pub(crate) fn store(env: &mut [Scope], var: Var, value: VarV) -> Result<(), RuntimeError> {
+    let frame = scope(env, var)?;
+    if frame.len() <= var.slot {
+        frame.resize(var.slot + 1, None);
+    }
+    frame[var.slot] = Some(value);
+    Ok(())
+}
+/// Closes the innermost block, returning the values left on its stack.
+fn leave(machine: &mut Machine, env: &mut Vec<Scope>) -> Result<Vec<VarV>, RuntimeError> {
+    let outer = machine.blocks.pop().ok_or(ErrorKind::StackUnderflow)?;
+    machine.entries.pop();
+    env.pop();
+    Ok(std::mem::replace(&mut machine.stack, outer))
 }
-fn pack(v: Vec<VarV>) -> VarV {
+/// Starts a function body in a fresh environment holding only its
+/// parameters, so the caller's variables stay untouched and recursion works.
+fn call(
+    name: usize,
+    argc: usize,
+    index: usize,
+    machine: &mut Machine,
+    env: &mut Vec<Scope>,
+    runtime: &mut Runtime,
+) -> Result<Flow, RuntimeError> {
+    let function = *runtime.functions.get(&name).ok_or(ErrorKind::UndefinedFunction(name))?;
+    if function.arity != argc {
+        return Err(ErrorKind::ArityMismatch { name, expected: function.arity, found: argc }.into());
+    }
+    if machine.stack.len() < argc {
+        return Err(ErrorKind::StackUnderflow.into());
+    }
+    if machine.calls.len() >= MAX_CALL_DEPTH {
+        return Err(ErrorKind::CallDepthExceeded(MAX_CALL_DEPTH).into());
+    }
+    let args = machine.stack.split_off(machine.stack.len() - argc);
+    machine.calls.push(Call {
+        function: name,
+        from: index,
+        env: std::mem::replace(env, vec![args.into_iter().map(Some).collect()]),
+        stack: std::mem::take(&mut machine.stack),
+        blocks: std::mem::take(&mut machine.blocks),
+        entries: std::mem::take(&mut machine.entries),
+    });
+    Ok(Flow::Goto(function.entry))
+}
+/// Goes back to the caller with `value`, or ends the program if there is
+/// none.
+fn ret(value: VarV, machine: &mut Machine, env: &mut Vec<Scope>) -> Flow {
+    let Some(call) = machine.calls.pop() else {
+        return Flow::Halt(value);
+    };
+    *env = call.env;
+    machine.stack = call.stack;
+    machine.blocks = call.blocks;
+    machine.entries = call.entries;
+    machine.stack.push(value);
+    Flow::Goto(call.from + 1)
+}
+pub(crate) fn pop(stack: &mut Vec<VarV>) -> Result<VarV, RuntimeError> {
+    stack.pop().ok_or(ErrorKind::StackUnderflow.into())
+}
+/// Whether `value` matches `pattern`, storing the parts it binds on the way.
+fn match_pattern(pattern: &MatchPattern, value: VarV, env: &mut [Scope]) -> Result<bool, RuntimeError> {
+    match (pattern, value) {
+        (MatchPattern::Var(var), value) => store(env, *var, value)?,
+        (MatchPattern::Val(val), value) => return Ok(value == *val),
+        (MatchPattern::Unused, _) => (),
+        (MatchPattern::Tuple(patterns), VarV::Tuple(items)) if patterns.len() == items.len() => {
+            for (pattern, item) in patterns.iter().zip(items) {
+                if !match_pattern(pattern, item, env)? {
+                    return Ok(false);
+                }
+            }
+        }
+        (MatchPattern::Tuple(_), _) => return Ok(false),
+    }
+    Ok(true)
+}
+pub(crate) fn pack(v: Vec<VarV>) -> VarV {
     match v.len() {
         0 => VarV::Tuple(Vec::new()),
         1 => v[0].clone(),
         _ => VarV::Tuple(v),
     }
 }
-fn unpack(v: VarV) -> Vec<VarV> {
+pub(crate) fn unpack(v: VarV) -> Vec<VarV> {
     match v {
         VarV::Tuple(vec) => vec,
         _ => vec![v],
     }
 }
-fn do_operation(stack: &mut Vec<VarV>, operation: IR)
+pub(crate) fn do_operation(stack: &mut Vec<VarV>, operation: &IR, overflow: Overflow) -> Result<(), RuntimeError>
 {
-    let a = stack.pop().unwrap();
+    let a = pop(stack)?;
     if let IR::Not = operation {
-        stack.push(!a);
-        return;
+        stack.push(a.negate(overflow)?);
+        return Ok(());
     }
-    let b = stack.pop().unwrap();
+    let b = pop(stack)?;
     stack.push(match operation {
-        IR::BinExpr(action) => match action {
-            ActionV::Add => b + a,
-            ActionV::Sub => b - a,
-            ActionV::Mul => b * a,
-            ActionV::Div => b / a,
-            ActionV::Mod => b % a,
-            _ => panic!("Unknown binary operation: {:?}", action),
-        },
-        IR::Or => b | a,
-        IR::And => b & a,
-        IR::Eql => VarV::Bool(a == b),
-        IR::NEql => VarV::Bool(a != b),
-        IR::Ls => VarV::Bool(b < a),
-        IR::Gt => VarV::Bool(b > a),
-        IR::LsEql => VarV::Bool(b <= a),
-        IR::GtEql => VarV::Bool(b >= a),
+        IR::BinExpr(action) => b.apply(action.clone(), a, overflow)?,
+        IR::Or => (b | a)?,
+        IR::And => (b & a)?,
+        IR::Eql => VarV::Bool(compare(&b, &a, &ComparsionV::Equal)?),
+        IR::NEql => VarV::Bool(compare(&b, &a, &ComparsionV::NotEqual)?),
+        IR::Ls => VarV::Bool(compare(&b, &a, &ComparsionV::Less)?),
+        IR::Gt => VarV::Bool(compare(&b, &a, &ComparsionV::Greater)?),
+        IR::LsEql => VarV::Bool(compare(&b, &a, &ComparsionV::LessOrEqual)?),
+        IR::GtEql => VarV::Bool(compare(&b, &a, &ComparsionV::GreaterOrEqual)?),
         _ => panic!("Unknown binary operation: {:?}", operation),
     });
-}
\ No newline at end of file
+    Ok(())
+}
+/// `left` compared to `right` the way the comparison instructions do it.
+fn compare(left: &VarV, right: &VarV, comparsion: &ComparsionV) -> Result<bool, RuntimeError> {
+    Ok(match comparsion {
+        ComparsionV::Equal => right == left,
+        ComparsionV::NotEqual => right != left,
+        ComparsionV::Less => comparable(left, right, comparsion.clone())? && left < right,
+        ComparsionV::Greater => comparable(left, right, comparsion.clone())? && left > right,
+        ComparsionV::LessOrEqual => comparable(left, right, comparsion.clone())? && left <= right,
+        ComparsionV::GreaterOrEqual => comparable(left, right, comparsion.clone())? && left >= right,
+    })
+}
+/// Ordering comparisons are defined between numbers (ints and floats mixed)
+/// and between strings. NaN compares false rather than failing.
+fn comparable(b: &VarV, a: &VarV, comparsion: ComparsionV) -> Result<bool, RuntimeError> {
+    match (b, a) {
+        (VarV::Str(_), VarV::Str(_)) => Ok(true),
+        (a, b) if a.is_number() && b.is_number() => Ok(true),
+        _ => Err(ErrorKind::IncomparableTypes { comparsion, left: b.type_name(), right: a.type_name() }.into()),
+    }
+}
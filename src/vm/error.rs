@@ -0,0 +1,138 @@
+use std::fmt;
+
+use crate::ir::NameText;
+use crate::types::{ActionV, ComparsionV, Var};
+use super::MAX_RANGE_LEN;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    StackUnderflow,
+    UninitializedVariable(Var),
+    UndefinedFunction(usize),
+    ArityMismatch { name: usize, expected: usize, found: usize },
+    CallDepthExceeded(usize),
+    TypeMismatch { action: ActionV, left: &'static str, right: Option<&'static str> },
+    IncomparableTypes { comparsion: ComparsionV, left: &'static str, right: &'static str },
+    DivisionByZero,
+    Overflow { action: ActionV, left: isize, right: isize },
+    InvalidJumpTarget(usize),
+    /// Indexing, slicing or taking the length of a value that is not a list
+    /// or string.
+    NotIndexable(&'static str),
+    /// An index or slice bound that is not an integer.
+    InvalidIndex(&'static str),
+    IndexOutOfRange { index: isize, len: usize },
+    /// A range with more than `MAX_RANGE_LEN` elements built as a list.
+    RangeTooLong { start: isize, end: isize },
+}
+/// One call that was active when the error occurred: the index of the
+/// instruction it was at, and the function, or none for the program itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub index: usize,
+    pub function: Option<usize>,
+    /// Index of the `Enter` of each block open in the call, innermost
+    /// first.
+    pub blocks: Vec<usize>,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    /// Active frames, innermost first.
+    pub trace: Vec<Frame>,
+}
+impl From<ErrorKind> for RuntimeError {
+    fn from(kind: ErrorKind) -> Self {
+        RuntimeError { kind, trace: Vec::new() }
+    }
+}
+impl ErrorKind {
+    /// Writes the message with variables and functions named from `names`.
+    fn write(&self, f: &mut fmt::Formatter<'_>, names: &[String]) -> fmt::Result {
+        match self {
+            ErrorKind::StackUnderflow => write!(f, "stack underflow"),
+            ErrorKind::UninitializedVariable(var) => {
+                write!(f, "uninitialized variable {}", NameText(var.name, names))
+            }
+            ErrorKind::UndefinedFunction(name) => write!(f, "undefined function {}", NameText(*name, names)),
+            ErrorKind::ArityMismatch { name, expected, found } => {
+                let name = NameText(*name, names);
+                write!(f, "function {} takes {} arguments but {} were given", name, expected, found)
+            }
+            ErrorKind::CallDepthExceeded(limit) => write!(f, "call depth exceeded {} nested calls", limit),
+            ErrorKind::TypeMismatch { action, left, right: Some(right) } => {
+                write!(f, "type mismatch: cannot apply {:?} to {} and {}", action, left, right)
+            }
+            ErrorKind::TypeMismatch { action, left, right: None } => {
+                write!(f, "type mismatch: cannot apply {:?} to {}", action, left)
+            }
+            ErrorKind::IncomparableTypes { comparsion, left, right } => {
+                write!(f, "type mismatch: cannot compare {} and {} with {:?}", left, right, comparsion)
+            }
+            ErrorKind::DivisionByZero => write!(f, "division by zero"),
+            ErrorKind::Overflow { action: ActionV::Not, right, .. } => {
+                write!(f, "integer overflow in Not: cannot negate {}", right)
+            }
+            ErrorKind::Overflow { action, left, right } => {
+                write!(f, "integer overflow in {:?}: {} and {}", action, left, right)
+            }
+            ErrorKind::InvalidJumpTarget(target) => write!(f, "jump target {} is out of range", target),
+            ErrorKind::NotIndexable(found) => write!(f, "type mismatch: {} cannot be indexed", found),
+            ErrorKind::InvalidIndex(found) => write!(f, "type mismatch: index must be an integer, found {}", found),
+            ErrorKind::IndexOutOfRange { index, len } => {
+                write!(f, "index {} is out of range for length {}", index, len)
+            }
+            ErrorKind::RangeTooLong { start, end } => {
+                write!(f, "range {} .. {} has more than {} elements", start, end, MAX_RANGE_LEN)
+            }
+        }
+    }
+}
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, &[])
+    }
+}
+/// Frames printed from each end of a long trace.
+const SHOWN_FRAMES: usize = 10;
+impl RuntimeError {
+    /// The error as printed, with the names the program's ids stand for.
+    pub fn named<'a>(&'a self, names: &'a [String]) -> Named<'a> {
+        Named { error: self, names }
+    }
+}
+/// A `RuntimeError` showing variables and functions by name.
+pub struct Named<'a> {
+    error: &'a RuntimeError,
+    names: &'a [String],
+}
+impl fmt::Display for Named<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Named { error, names } = self;
+        write!(f, "runtime error: ")?;
+        error.kind.write(f, names)?;
+        let trace = &error.trace;
+        for (depth, frame) in trace.iter().enumerate() {
+            // deep recursion would print thousands of identical frames
+            if depth >= SHOWN_FRAMES && trace.len() - depth > SHOWN_FRAMES {
+                if depth == SHOWN_FRAMES {
+                    write!(f, "\n  ... {} frames omitted", trace.len() - 2 * SHOWN_FRAMES)?;
+                }
+                continue;
+            }
+            write!(f, "\n  {}: at instruction {}", trace.len() - depth - 1, frame.index)?;
+            if let Some(function) = frame.function {
+                write!(f, " in function {}", NameText(function, names))?;
+            }
+            for block in &frame.blocks {
+                write!(f, "\n       block frame at instruction {}", block)?;
+            }
+        }
+        Ok(())
+    }
+}
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.named(&[]))
+    }
+}
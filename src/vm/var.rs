@@ -1,12 +1,159 @@
-use std::ops;
+use std::{fmt, ops};
 
-use super::VarV;
+use super::{ErrorKind, Overflow, RuntimeError, VarV};
+use crate::types::ActionV;
+#[cfg(feature = "bigint")]
+mod big;
+mod list;
 
-impl Ord for VarV {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+impl VarV {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            VarV::Tuple(_) => "tuple",
+            VarV::List(_) => "list",
+            VarV::Num(_) => "num",
+            #[cfg(feature = "bigint")]
+            VarV::Big(_) => "num",
+            VarV::Float(_) => "float",
+            VarV::Bool(_) => "bool",
+            VarV::Str(_) => "str",
+        }
+    }
+    pub fn is_number(&self) -> bool {
+        match self {
+            VarV::Num(_) | VarV::Float(_) => true,
+            #[cfg(feature = "bigint")]
+            VarV::Big(_) => true,
+            _ => false,
+        }
+    }
+    fn mismatch(action: ActionV, left: &VarV, right: &VarV) -> RuntimeError {
+        ErrorKind::TypeMismatch { action, left: left.type_name(), right: Some(right.type_name()) }.into()
+    }
+    /// Both operands as floats if at least one is a float and the other is a
+    /// number; ints are promoted.
+    fn floats(left: &VarV, right: &VarV) -> Option<(f64, f64)> {
+        match (left, right) {
+            (VarV::Float(a), VarV::Float(b)) => Some((*a, *b)),
+            (VarV::Num(a), VarV::Float(b)) => Some((*a as f64, *b)),
+            (VarV::Float(a), VarV::Num(b)) => Some((*a, *b as f64)),
+            #[cfg(feature = "bigint")]
+            (VarV::Big(a), VarV::Float(b)) => Some((big::to_float(a), *b)),
+            #[cfg(feature = "bigint")]
+            (VarV::Float(a), VarV::Big(b)) => Some((*a, big::to_float(b))),
+            _ => None,
+        }
+    }
+    /// Binary arithmetic with the given overflow policy for integers; every
+    /// other combination of operands goes through the `ops` impls.
+    pub fn apply(self, action: ActionV, other: VarV, overflow: Overflow) -> Result<VarV, RuntimeError> {
+        match (self, other, &action) {
+            (VarV::Num(a), VarV::Num(b), _) => integer(action, a, b, overflow),
+            #[cfg(feature = "bigint")]
+            (a, b, _) if big::pair(&a, &b).is_some() => {
+                let (a, b) = big::pair(&a, &b).unwrap();
+                big::apply(action, a, b)
+            }
+            (a, b, ActionV::Add) => a + b,
+            (a, b, ActionV::Sub) => a - b,
+            (a, b, ActionV::Mul) => a * b,
+            (a, b, ActionV::Div) => a / b,
+            (a, b, ActionV::Mod) => a % b,
+            (a, b, _) => Err(VarV::mismatch(action, &a, &b)),
+        }
+    }
+    pub fn negate(self, overflow: Overflow) -> Result<VarV, RuntimeError> {
+        match self {
+            VarV::Num(v) => match overflow {
+                Overflow::Trap => v
+                    .checked_neg()
+                    .map(VarV::Num)
+                    .ok_or(ErrorKind::Overflow { action: ActionV::Not, left: 0, right: v }.into()),
+                Overflow::Wrap => Ok(VarV::Num(v.wrapping_neg())),
+                Overflow::Saturate => Ok(VarV::Num(v.saturating_neg())),
+                #[cfg(feature = "bigint")]
+                Overflow::Promote => Ok(big::normalize(-num_bigint::BigInt::from(v))),
+            },
+            #[cfg(feature = "bigint")]
+            VarV::Big(v) => Ok(big::normalize(-v)),
+            v => !v,
+        }
+    }
+}
+fn integer(action: ActionV, a: isize, b: isize, overflow: Overflow) -> Result<VarV, RuntimeError> {
+    if b == 0 && matches!(action, ActionV::Div | ActionV::Mod) {
+        return Err(ErrorKind::DivisionByZero.into());
+    }
+    let result = match overflow {
+        Overflow::Trap => match action {
+            ActionV::Add => a.checked_add(b),
+            ActionV::Sub => a.checked_sub(b),
+            ActionV::Mul => a.checked_mul(b),
+            ActionV::Div => a.checked_div(b),
+            ActionV::Mod => a.checked_rem(b),
+            _ => return Err(VarV::mismatch(action, &VarV::Num(a), &VarV::Num(b))),
+        },
+        Overflow::Wrap => match action {
+            ActionV::Add => Some(a.wrapping_add(b)),
+            ActionV::Sub => Some(a.wrapping_sub(b)),
+            ActionV::Mul => Some(a.wrapping_mul(b)),
+            ActionV::Div => Some(a.wrapping_div(b)),
+            ActionV::Mod => Some(a.wrapping_rem(b)),
+            _ => return Err(VarV::mismatch(action, &VarV::Num(a), &VarV::Num(b))),
+        },
+        Overflow::Saturate => match action {
+            ActionV::Add => Some(a.saturating_add(b)),
+            ActionV::Sub => Some(a.saturating_sub(b)),
+            ActionV::Mul => Some(a.saturating_mul(b)),
+            ActionV::Div => Some(a.saturating_div(b)),
+            // the only overflowing case, MIN % -1, is mathematically 0
+            ActionV::Mod => Some(a.wrapping_rem(b)),
+            _ => return Err(VarV::mismatch(action, &VarV::Num(a), &VarV::Num(b))),
+        },
+        #[cfg(feature = "bigint")]
+        Overflow::Promote => return match integer(action.clone(), a, b, Overflow::Trap) {
+            Err(RuntimeError { kind: ErrorKind::Overflow { .. }, .. }) => big::apply(action, a.into(), b.into()),
+            result => result,
+        },
+    };
+    result.map(VarV::Num).ok_or(ErrorKind::Overflow { action, left: a, right: b }.into())
+}
+impl fmt::Display for VarV {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VarV::Num(val) => write!(f, "{}", val),
+            #[cfg(feature = "bigint")]
+            VarV::Big(val) => write!(f, "{}", val),
+            VarV::Float(val) => write!(f, "{:?}", val),
+            VarV::Bool(val) => write!(f, "{}", val),
+            VarV::Str(val) => write!(f, "{}", val),
+            VarV::Tuple(items) => write_items(f, "(", items, ")"),
+            VarV::List(items) => write_items(f, "[", items, "]"),
+        }
+    }
+}
+/// `items` separated by commas between `open` and `close`.
+fn write_items(f: &mut fmt::Formatter<'_>, open: &str, items: &[VarV], close: &str) -> fmt::Result {
+    write!(f, "{}", open)?;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    write!(f, "{}", close)
+}
+impl PartialEq for VarV {
+    fn eq(&self, other: &Self) -> bool {
         match (self, other) {
-            (VarV::Num(a), VarV::Num(b)) => a.cmp(b),
-            _ => panic!("Type mismatch"),
+            (VarV::Num(a), VarV::Num(b)) => a == b,
+            (VarV::Bool(a), VarV::Bool(b)) => a == b,
+            (VarV::Str(a), VarV::Str(b)) => a == b,
+            (VarV::Tuple(a), VarV::Tuple(b)) => a == b,
+            (VarV::List(a), VarV::List(b)) => a == b,
+            #[cfg(feature = "bigint")]
+            (a, b) if big::pair(a, b).is_some() => big::pair(a, b).is_some_and(|(a, b)| a == b),
+            (a, b) => VarV::floats(a, b).is_some_and(|(a, b)| a == b),
         }
     }
 }
@@ -14,83 +161,110 @@ impl PartialOrd for VarV {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         match (self, other) {
             (VarV::Num(a), VarV::Num(b)) => a.partial_cmp(b),
-            _ => None,
+            (VarV::Str(a), VarV::Str(b)) => a.partial_cmp(b),
+            #[cfg(feature = "bigint")]
+            (a, b) if big::pair(a, b).is_some() => big::pair(a, b).and_then(|(a, b)| a.partial_cmp(&b)),
+            (a, b) => VarV::floats(a, b).and_then(|(a, b)| a.partial_cmp(&b)),
         }
     }
 }
 impl ops::Not for VarV {
-    type Output = Self;
-    fn not(self) -> Self {
+    type Output = Result<Self, RuntimeError>;
+    fn not(self) -> Self::Output {
         match self {
-            VarV::Bool(b) => VarV::Bool(!b),
-            VarV::Num(v) => VarV::Num(-v),
-            VarV::Tuple(_) => todo!(),
+            VarV::Bool(b) => Ok(VarV::Bool(!b)),
+            VarV::Num(_) => self.negate(Overflow::Trap),
+            #[cfg(feature = "bigint")]
+            VarV::Big(_) => self.negate(Overflow::Trap),
+            VarV::Float(v) => Ok(VarV::Float(-v)),
+            VarV::Tuple(_) | VarV::List(_) | VarV::Str(_) => Err(ErrorKind::TypeMismatch { action: ActionV::Not, left: self.type_name(), right: None }.into()),
         }
     }
 }
 impl ops::Add for VarV {
-    type Output = Self;
-    fn add(self, other: Self) -> Self {
+    type Output = Result<Self, RuntimeError>;
+    fn add(self, other: Self) -> Self::Output {
         match (self, other) {
-            (VarV::Num(a), VarV::Num(b)) => VarV::Num(a + b),
-            (VarV::Bool(a), VarV::Bool(b)) => VarV::Bool(a || b),
-            _ => panic!("Type mismatch"),
+            (VarV::Num(a), VarV::Num(b)) => integer(ActionV::Add, a, b, Overflow::Trap),
+            (VarV::Bool(a), VarV::Bool(b)) => Ok(VarV::Bool(a || b)),
+            (VarV::List(mut a), VarV::List(b)) => {
+                a.extend(b);
+                Ok(VarV::List(a))
+            }
+            (VarV::Str(a), b @ (VarV::Str(_) | VarV::Num(_) | VarV::Float(_) | VarV::Bool(_))) => Ok(VarV::Str(format!("{}{}", a, b))),
+            (a @ (VarV::Num(_) | VarV::Float(_) | VarV::Bool(_)), VarV::Str(b)) => Ok(VarV::Str(format!("{}{}", a, b))),
+            (a, b) => match VarV::floats(&a, &b) {
+                Some((a, b)) => Ok(VarV::Float(a + b)),
+                None => Err(VarV::mismatch(ActionV::Add, &a, &b)),
+            },
         }
     }
 }
 impl ops::Sub for VarV {
-    type Output = Self;
-    fn sub(self, other: Self) -> Self {
+    type Output = Result<Self, RuntimeError>;
+    fn sub(self, other: Self) -> Self::Output {
         match (self, other) {
-            (VarV::Num(a), VarV::Num(b)) => VarV::Num(a - b),
-            _ => panic!("Type mismatch"),
+            (VarV::Num(a), VarV::Num(b)) => integer(ActionV::Sub, a, b, Overflow::Trap),
+            (a, b) => match VarV::floats(&a, &b) {
+                Some((a, b)) => Ok(VarV::Float(a - b)),
+                None => Err(VarV::mismatch(ActionV::Sub, &a, &b)),
+            },
         }
     }
 }
 impl ops::Mul for VarV {
-    type Output = Self;
-    fn mul(self, other: Self) -> Self {
+    type Output = Result<Self, RuntimeError>;
+    fn mul(self, other: Self) -> Self::Output {
         match (self, other) {
-            (VarV::Num(a), VarV::Num(b)) => VarV::Num(a * b),
-            (VarV::Bool(a), VarV::Bool(b)) => VarV::Bool(a && b),
-            _ => panic!("Type mismatch"),
+            (VarV::Num(a), VarV::Num(b)) => integer(ActionV::Mul, a, b, Overflow::Trap),
+            (VarV::Bool(a), VarV::Bool(b)) => Ok(VarV::Bool(a && b)),
+            (a, b) => match VarV::floats(&a, &b) {
+                Some((a, b)) => Ok(VarV::Float(a * b)),
+                None => Err(VarV::mismatch(ActionV::Mul, &a, &b)),
+            },
         }
     }
 }
 impl ops::Div for VarV {
-    type Output = Self;
-    fn div(self, other: Self) -> Self {
+    type Output = Result<Self, RuntimeError>;
+    fn div(self, other: Self) -> Self::Output {
         match (self, other) {
-            (VarV::Num(a), VarV::Num(b)) => VarV::Num(a / b),
-            _ => panic!("Type mismatch"),
+            (VarV::Num(a), VarV::Num(b)) => integer(ActionV::Div, a, b, Overflow::Trap),
+            (a, b) => match VarV::floats(&a, &b) {
+                Some((a, b)) => Ok(VarV::Float(a / b)),
+                None => Err(VarV::mismatch(ActionV::Div, &a, &b)),
+            },
         }
     }
 }
 impl ops::Rem for VarV {
-    type Output = Self;
-    fn rem(self, other: Self) -> Self {
+    type Output = Result<Self, RuntimeError>;
+    fn rem(self, other: Self) -> Self::Output {
         match (self, other) {
-            (VarV::Num(a), VarV::Num(b)) => VarV::Num(a % b),
-            _ => panic!("Type mismatch"),
+            (VarV::Num(a), VarV::Num(b)) => integer(ActionV::Mod, a, b, Overflow::Trap),
+            (a, b) => match VarV::floats(&a, &b) {
+                Some((a, b)) => Ok(VarV::Float(a % b)),
+                None => Err(VarV::mismatch(ActionV::Mod, &a, &b)),
+            },
         }
     }
-    
+
 }
 impl ops::BitOr for VarV {
-    type Output = Self;
-    fn bitor(self, other: Self) -> Self {
+    type Output = Result<Self, RuntimeError>;
+    fn bitor(self, other: Self) -> Self::Output {
         match (self, other) {
-            (VarV::Bool(a), VarV::Bool(b)) => VarV::Bool(a || b),
-            _ => panic!("Type mismatch"),
+            (VarV::Bool(a), VarV::Bool(b)) => Ok(VarV::Bool(a || b)),
+            (a, b) => Err(VarV::mismatch(ActionV::Or, &a, &b)),
         }
     }
 }
 impl ops::BitAnd for VarV {
-    type Output = Self;
-    fn bitand(self, other: Self) -> Self {
+    type Output = Result<Self, RuntimeError>;
+    fn bitand(self, other: Self) -> Self::Output {
         match (self, other) {
-            (VarV::Bool(a), VarV::Bool(b)) => VarV::Bool(a && b),
-            _ => panic!("Type mismatch"),
+            (VarV::Bool(a), VarV::Bool(b)) => Ok(VarV::Bool(a && b)),
+            (a, b) => Err(VarV::mismatch(ActionV::And, &a, &b)),
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,39 @@
+use num_bigint::BigInt;
+use num_traits::{ToPrimitive, Zero};
+
+use super::super::{ErrorKind, RuntimeError, VarV};
+use crate::types::ActionV;
+
+/// Both operands as big integers, if both are integers and at least one of
+/// them is already a `VarV::Big`.
+pub fn pair(left: &VarV, right: &VarV) -> Option<(BigInt, BigInt)> {
+    match (left, right) {
+        (VarV::Big(a), VarV::Big(b)) => Some((a.clone(), b.clone())),
+        (VarV::Big(a), VarV::Num(b)) => Some((a.clone(), BigInt::from(*b))),
+        (VarV::Num(a), VarV::Big(b)) => Some((BigInt::from(*a), b.clone())),
+        _ => None,
+    }
+}
+pub fn apply(action: ActionV, a: BigInt, b: BigInt) -> Result<VarV, RuntimeError> {
+    if b.is_zero() && matches!(action, ActionV::Div | ActionV::Mod) {
+        return Err(ErrorKind::DivisionByZero.into());
+    }
+    Ok(normalize(match action {
+        ActionV::Add => a + b,
+        ActionV::Sub => a - b,
+        ActionV::Mul => a * b,
+        ActionV::Div => a / b,
+        ActionV::Mod => a % b,
+        _ => return Err(VarV::mismatch(action, &VarV::Big(a), &VarV::Big(b))),
+    }))
+}
+/// Demotes results that fit into `isize` back to `VarV::Num`.
+pub fn normalize(value: BigInt) -> VarV {
+    match value.to_isize() {
+        Some(val) => VarV::Num(val),
+        None => VarV::Big(value),
+    }
+}
+pub fn to_float(value: &BigInt) -> f64 {
+    value.to_f64().unwrap_or(f64::NAN)
+}
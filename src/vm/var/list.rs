@@ -0,0 +1,109 @@
+use super::super::{ErrorKind, RuntimeError, VarV, MAX_RANGE_LEN};
+
+impl VarV {
+    /// An element of a list or a character of a string. Negative indices
+    /// count from the end.
+    pub fn index(self, index: &VarV) -> Result<VarV, RuntimeError> {
+        match self {
+            VarV::List(mut items) => {
+                let i = position(index, items.len())?;
+                Ok(items.swap_remove(i))
+            }
+            VarV::Str(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                let i = position(index, chars.len())?;
+                Ok(VarV::Str(chars[i].to_string()))
+            }
+            value => Err(ErrorKind::NotIndexable(value.type_name()).into()),
+        }
+    }
+    /// Elements from `start` up to, not including, `end`. Bounds are clamped
+    /// to the value, so a slice is never out of range.
+    pub fn slice(self, start: Option<&VarV>, end: Option<&VarV>) -> Result<VarV, RuntimeError> {
+        match self {
+            VarV::List(items) => {
+                let (start, end) = bounds(start, end, items.len())?;
+                Ok(VarV::List(items[start..end].to_vec()))
+            }
+            VarV::Str(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                let (start, end) = bounds(start, end, chars.len())?;
+                Ok(VarV::Str(chars[start..end].iter().collect()))
+            }
+            value => Err(ErrorKind::NotIndexable(value.type_name()).into()),
+        }
+    }
+    /// The integers from `self` up to but excluding `end`, at most
+    /// `MAX_RANGE_LEN` of them.
+    pub fn range(&self, end: &VarV) -> Result<VarV, RuntimeError> {
+        let (start, end) = (integer(self)?, integer(end)?);
+        if end.saturating_sub(start) > MAX_RANGE_LEN as isize {
+            return Err(ErrorKind::RangeTooLong { start, end }.into());
+        }
+        Ok(VarV::List((start..end).map(VarV::Num).collect()))
+    }
+    pub fn len(self) -> Result<VarV, RuntimeError> {
+        match self {
+            VarV::List(items) => Ok(VarV::Num(items.len() as isize)),
+            VarV::Str(s) => Ok(VarV::Num(s.chars().count() as isize)),
+            value => Err(ErrorKind::NotIndexable(value.type_name()).into()),
+        }
+    }
+    /// The list with the element at `indices`, one per nesting level,
+    /// replaced by `value`.
+    pub fn set_item(self, indices: &[VarV], value: VarV) -> Result<VarV, RuntimeError> {
+        let Some((index, rest)) = indices.split_first() else {
+            return Ok(value);
+        };
+        match self {
+            VarV::List(mut items) => {
+                let i = position(index, items.len())?;
+                let item = std::mem::replace(&mut items[i], VarV::Tuple(Vec::new()));
+                items[i] = item.set_item(rest, value)?;
+                Ok(VarV::List(items))
+            }
+            value => Err(ErrorKind::NotIndexable(value.type_name()).into()),
+        }
+    }
+}
+fn integer(index: &VarV) -> Result<isize, RuntimeError> {
+    match index {
+        VarV::Num(i) => Ok(*i),
+        _ => Err(ErrorKind::InvalidIndex(index.type_name()).into()),
+    }
+}
+fn position(index: &VarV, len: usize) -> Result<usize, RuntimeError> {
+    let i = integer(index)?;
+    let resolved = if i < 0 { i + len as isize } else { i };
+    if resolved < 0 || resolved as usize >= len {
+        return Err(ErrorKind::IndexOutOfRange { index: i, len }.into());
+    }
+    Ok(resolved as usize)
+}
+fn bounds(start: Option<&VarV>, end: Option<&VarV>, len: usize) -> Result<(usize, usize), RuntimeError> {
+    let clamp = |bound: Option<&VarV>, default: usize| -> Result<usize, RuntimeError> {
+        let Some(bound) = bound else {
+            return Ok(default);
+        };
+        let i = integer(bound)?;
+        let resolved = if i < 0 { i.saturating_add(len as isize) } else { i };
+        Ok(resolved.clamp(0, len as isize) as usize)
+    };
+    let start = clamp(start, 0)?;
+    let end = clamp(end, len)?;
+    Ok((start, end.max(start)))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_to_build_a_huge_range() {
+        let error = VarV::Num(0).range(&VarV::Num(1_000_000_000)).expect_err("huge range is built");
+        assert_eq!(error.kind, ErrorKind::RangeTooLong { start: 0, end: 1_000_000_000 });
+        let error = VarV::Num(isize::MIN).range(&VarV::Num(isize::MAX)).expect_err("huge range is built");
+        assert_eq!(error.kind, ErrorKind::RangeTooLong { start: isize::MIN, end: isize::MAX });
+        let longest = VarV::Num(-1).range(&VarV::Num(MAX_RANGE_LEN as isize - 1)).expect("longest range fails");
+        assert_eq!(longest.len().expect("range has no length"), VarV::Num(MAX_RANGE_LEN as isize));
+    }
+}